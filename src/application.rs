@@ -1,135 +1,673 @@
 use freedesktop_entry_parser::parse_entry;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use std::collections::HashMap;
 
+use crate::error::LauncherError;
 use crate::searchable_list::Application;
-use crate::utility::get_xdg_application_dirs;
+use crate::utility::{
+    find_executable, get_messages_locale, get_xdg_application_dirs, get_xdg_current_desktops,
+};
 use cached::proc_macro::cached;
+use log::warn;
 use std::fs;
 
-/// replace the format specifiers - most get replaced with nothing because they're for parameters or deprecated
-/// %i is replaced with the Icon key, %c is replaced with the name, %k is replaced with the URI
+/// undo the escapes allowed in any desktop entry string value (`\s`, `\n`, `\t`, `\r` and `\\`)
+fn unescape_string(value: &str) -> String {
+    let mut result = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('s') => result.push(' '),
+            Some('n') => result.push('\n'),
+            Some('t') => result.push('\t'),
+            Some('r') => result.push('\r'),
+            Some('\\') => result.push('\\'),
+            Some(x) => {
+                result.push('\\');
+                result.push(x);
+            }
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// split an Exec key into an argument vector following the quoting rules of the desktop entry spec and expand its field codes
+/// the file and url codes are dropped because we never launch with arguments, %i becomes `--icon <icon>`,
+/// %c is replaced with the name, %k is replaced with the URI and %% with a literal %
 #[cached]
-fn filter_exec(exec: String, icon: String, name: String, uri: String) -> String {
-    exec.replace("%F", "")
-        .replace("%f", "")
-        .replace("%U", "")
-        .replace("%u", "")
-        .replace("%D", "")
-        .replace("%d", "")
-        .replace("%v", "")
-        .replace("%n", "")
-        .replace("%N", "")
-        .replace("%m", "")
-        .replace("%i", &icon)
-        .replace("%c", &name)
-        .replace("%k", &uri)
+fn filter_exec(
+    exec: String,
+    icon: String,
+    name: String,
+    uri: String,
+) -> Result<Vec<String>, LauncherError> {
+    let chars = unescape_string(&exec).chars().collect::<Vec<char>>();
+    let mut args = vec![];
+    // the argument being built and whether it must be kept even if it ends up empty (e.g. `""`)
+    let mut current = String::new();
+    let mut keep = false;
+    let mut quoted = false;
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        i += 1;
+        match c {
+            ' ' | '\t' | '\n' if !quoted => {
+                if keep || !current.is_empty() {
+                    args.push(current.clone());
+                }
+                current.clear();
+                keep = false;
+            }
+            '"' => {
+                quoted = !quoted;
+                keep = true;
+            }
+            '\\' if i < chars.len() && (!quoted || ['"', '`', '$', '\\'].contains(&chars[i])) => {
+                current.push(chars[i]);
+                keep = true;
+                i += 1;
+            }
+            '%' => {
+                match chars.get(i) {
+                    Some('%') => {
+                        current.push('%');
+                        keep = true;
+                    }
+                    Some('f') | Some('F') | Some('u') | Some('U') | Some('d') | Some('D')
+                    | Some('n') | Some('N') | Some('v') | Some('m') => {}
+                    Some('i') => {
+                        let standalone = current.is_empty()
+                            && !keep
+                            && !quoted
+                            && chars.get(i + 1).is_none_or(|x| x.is_whitespace());
+                        if standalone && !icon.is_empty() {
+                            args.push("--icon".into());
+                            args.push(icon.clone());
+                        } else if !standalone {
+                            current.push_str(&icon);
+                        }
+                    }
+                    Some('c') => current.push_str(&name),
+                    Some('k') => current.push_str(&uri),
+                    _ => return Err(LauncherError::ParseExecError),
+                }
+                i += 1;
+            }
+            x => {
+                current.push(x);
+                keep = true;
+            }
+        }
+    }
+    if quoted {
+        return Err(LauncherError::ParseExecError);
+    }
+    if keep || !current.is_empty() {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+/// split a desktop entry list value (e.g. `GNOME;KDE;`) into its elements
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(';')
+        .filter(|x| !x.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// decide whether an entry should be shown given its OnlyShowIn / NotShowIn lists and the desktops in XDG_CURRENT_DESKTOP
+/// an entry restricted with OnlyShowIn is hidden when none of the current desktops are listed
+fn is_shown_in(only_show_in: &[String], not_show_in: &[String], desktops: &[String]) -> bool {
+    if desktops.iter().any(|x| not_show_in.contains(x)) {
+        return false;
+    }
+    only_show_in.is_empty() || desktops.iter().any(|x| only_show_in.contains(x))
+}
+
+/// the locale keys to try for a localized value, most specific first
+/// `lang_COUNTRY.ENCODING@MODIFIER` yields `lang_COUNTRY@MODIFIER`, `lang_COUNTRY`, `lang@MODIFIER` and `lang`
+fn locale_variants(locale: &str) -> Vec<String> {
+    if locale.is_empty() || locale == "C" || locale == "POSIX" {
+        return vec![];
+    }
+    let (rest, modifier) = match locale.find('@') {
+        Some(i) => (&locale[..i], Some(&locale[i + 1..])),
+        None => (locale, None),
+    };
+    let rest = rest.split('.').next().unwrap_or(rest);
+    let (lang, country) = match rest.find('_') {
+        Some(i) => (&rest[..i], Some(&rest[i + 1..])),
+        None => (rest, None),
+    };
+
+    let mut variants = vec![];
+    if let (Some(country), Some(modifier)) = (country, modifier) {
+        variants.push(format!("{}_{}@{}", lang, country, modifier));
+    }
+    if let Some(country) = country {
+        variants.push(format!("{}_{}", lang, country));
+    }
+    if let Some(modifier) = modifier {
+        variants.push(format!("{}@{}", lang, modifier));
+    }
+    variants.push(lang.to_string());
+    variants
+}
+
+/// look up the best localized value for a key (e.g. `Name[de_DE]`), falling back on the untranslated key
+fn localized_value<'a>(
+    attributes: &'a HashMap<String, String>,
+    key: &str,
+    locales: &[String],
+) -> Option<&'a String> {
+    locales
+        .iter()
+        .find_map(|x| attributes.get(&format!("{}[{}]", key, x)))
+        .or_else(|| attributes.get(key))
 }
 
 #[cached]
 pub fn parse_desktop_entry(filename: PathBuf) -> Application {
-    let results = parse_entry(&fs::read_to_string(filename.as_path()).unwrap().into_bytes())
+    let contents = match fs::read(filename.as_path()) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("Couldn't read desktop entry {}: {}", filename.display(), e);
+            return Application::default();
+        }
+    };
+    // the parser doesn't advance past a malformed section so stop at the first error
+    let groups = parse_entry(&contents)
+        .map(|y| y.map_err(|e| warn!("Malformed desktop entry {}: {}", filename.display(), e)))
+        .take_while(|y| y.is_ok())
         .filter_map(|y| y.ok())
-        .filter(|y| y.title == b"Desktop Entry")
         .map(|y| {
             let attributes = y
                 .attrs
                 .iter()
                 .map(|z| {
                     (
-                        String::from_utf8_lossy(z.name).to_string(),
-                        String::from_utf8_lossy(z.value).to_string(),
+                        String::from_utf8_lossy(z.name).trim().to_string(),
+                        String::from_utf8_lossy(z.value).trim().to_string(),
                     )
                 })
-                .filter(|x| x.0 == "Name" || x.0 == "Icon" || x.0 == "Exec")
                 .collect::<HashMap<String, String>>();
-            let name = attributes
-                .get("Name")
-                .unwrap_or(&"".to_string())
-                .to_string();
-
-            let icon = attributes
-                .get("Icon")
-                .unwrap_or(&"application-x-executable".to_string())
-                .to_string();
-
-            let exec_pre = attributes
-                .get("Exec")
-                .unwrap_or(&"".to_string())
-                .to_string();
-
-            let exec = filter_exec(
-                exec_pre,
-                icon.clone(),
-                name.clone(),
-                filename.to_string_lossy().into(),
+            (String::from_utf8_lossy(y.title).to_string(), attributes)
+        })
+        .collect::<HashMap<String, HashMap<String, String>>>();
+    let attributes = match groups.get("Desktop Entry") {
+        Some(x) => x,
+        None => return Application::default(),
+    };
+    let locales = locale_variants(&get_messages_locale());
+
+    let untranslated_name = attributes
+        .get("Name")
+        .unwrap_or(&"".to_string())
+        .to_string();
+    let name = localized_value(attributes, "Name", &locales)
+        .unwrap_or(&untranslated_name)
+        .to_string();
+
+    let icon = attributes
+        .get("Icon")
+        .unwrap_or(&"application-x-executable".to_string())
+        .to_string();
+
+    let exec_pre = attributes
+        .get("Exec")
+        .unwrap_or(&"".to_string())
+        .to_string();
+
+    let args = filter_exec(
+        exec_pre.clone(),
+        icon.clone(),
+        name.clone(),
+        filename.to_string_lossy().into(),
+    )
+    .unwrap_or_else(|e| {
+        warn!("Couldn't parse Exec of {}: {}", filename.display(), e);
+        vec![]
+    });
+
+    let no_display = attributes.get("NoDisplay").is_some_and(|x| x == "true");
+    let hidden = attributes.get("Hidden").is_some_and(|x| x == "true");
+    let only_show_in = split_list(attributes.get("OnlyShowIn").unwrap_or(&"".to_string()));
+    let not_show_in = split_list(attributes.get("NotShowIn").unwrap_or(&"".to_string()));
+
+    let mut app = Application::new(name, icon, exec_pre, "".into());
+    app.args = args;
+    app.untranslated_name = untranslated_name;
+    app.generic_name = localized_value(attributes, "GenericName", &locales)
+        .unwrap_or(&"".to_string())
+        .to_string();
+    app.comment = localized_value(attributes, "Comment", &locales)
+        .unwrap_or(&"".to_string())
+        .to_string();
+    app.keywords =
+        split_list(localized_value(attributes, "Keywords", &locales).unwrap_or(&"".to_string()));
+    app.terminal = attributes.get("Terminal").is_some_and(|x| x == "true");
+    app.dbus_activatable = attributes
+        .get("DBusActivatable")
+        .is_some_and(|x| x == "true");
+    app.startup_notify = attributes.get("StartupNotify").is_some_and(|x| x == "true");
+    app.startup_wm_class = attributes
+        .get("StartupWMClass")
+        .unwrap_or(&"".to_string())
+        .to_string();
+    app.categories = split_list(attributes.get("Categories").unwrap_or(&"".to_string()));
+    app.working_dir = attributes
+        .get("Path")
+        .unwrap_or(&"".to_string())
+        .to_string();
+    // TryExec names a binary that has to be installed for the entry to be usable, stale entries of removed packages fail this
+    let installed = attributes
+        .get("TryExec")
+        .is_none_or(|x| find_executable(&unescape_string(x)).is_some());
+    app.visible = !no_display
+        && !hidden
+        && installed
+        && is_shown_in(&only_show_in, &not_show_in, &get_xdg_current_desktops());
+
+    app.actions = split_list(attributes.get("Actions").unwrap_or(&"".to_string()))
+        .iter()
+        .filter_map(|x| Some((x, groups.get(&format!("Desktop Action {}", x))?)))
+        .filter_map(|(action, attributes)| {
+            parse_desktop_action(&app, action, attributes, &locales, &filename)
+        })
+        .collect();
+    app
+}
+
+/// build the entry for a `[Desktop Action]` group - it is named after its parent application and shares its icon unless it has its own
+fn parse_desktop_action(
+    parent: &Application,
+    action: &str,
+    attributes: &HashMap<String, String>,
+    locales: &[String],
+    filename: &Path,
+) -> Option<Application> {
+    let untranslated_name = attributes.get("Name")?;
+    let name = localized_value(attributes, "Name", locales).unwrap_or(untranslated_name);
+
+    let icon = attributes.get("Icon").unwrap_or(&parent.icon).to_string();
+    let exec = attributes
+        .get("Exec")
+        .unwrap_or(&"".to_string())
+        .to_string();
+    let args = filter_exec(
+        exec.clone(),
+        icon.clone(),
+        parent.name.clone(),
+        filename.to_string_lossy().into(),
+    )
+    .unwrap_or_else(|e| {
+        warn!(
+            "Couldn't parse Exec of {} action {}: {}",
+            filename.display(),
+            action,
+            e
+        );
+        vec![]
+    });
+
+    let mut app = Application::new(format!("{}: {}", parent.name, name), icon, exec, "".into());
+    app.args = args;
+    app.untranslated_name = format!("{}: {}", parent.untranslated_name, untranslated_name);
+    app.action = action.to_string();
+    app.terminal = parent.terminal;
+    app.dbus_activatable = parent.dbus_activatable;
+    app.startup_notify = parent.startup_notify;
+    app.startup_wm_class = parent.startup_wm_class.clone();
+    app.working_dir = parent.working_dir.clone();
+    app.visible = parent.visible;
+    Some(app)
+}
+
+/// compute the desktop file id of a file relative to the applications directory it was found in
+/// e.g. `/usr/share/applications/kde/foo.desktop` has the id `kde-foo.desktop`
+fn desktop_file_id(base: &Path, path: &Path) -> Option<String> {
+    let relative = path.strip_prefix(base).ok()?;
+    Some(
+        relative
+            .components()
+            .map(|x| x.as_os_str().to_string_lossy().to_string())
+            .collect::<Vec<String>>()
+            .join("-"),
+    )
+}
+
+/// recursively find every .desktop file under an applications directory
+fn find_desktop_files(dir: &Path) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(
+                "Couldn't read application directory {}: {}",
+                dir.display(),
+                e
             );
-            Application::new(name, icon, exec, "".into())
+            return vec![];
+        }
+    };
+    entries
+        .filter_map(|x| x.ok())
+        .flat_map(|x| {
+            let path = x.path();
+            if x.file_type().is_ok_and(|y| y.is_dir()) {
+                find_desktop_files(&path)
+            } else if path.extension().is_some_and(|y| y == "desktop") && path.is_file() {
+                vec![path]
+            } else {
+                vec![]
+            }
         })
-        .next();
-    results.unwrap_or(Application::default())
+        .collect()
 }
 
+/// build the list of launchable applications keyed by desktop file id
+/// directories earlier in the search path shadow entries with the same id in later ones, even if the shadowing entry is hidden
 #[cached]
 pub fn generate_application_list() -> HashMap<String, Application> {
+    let mut applications = HashMap::new();
     get_xdg_application_dirs()
         .filter(|x| x.exists())
         .flat_map(|path| {
-            fs::read_dir(path)
-                .unwrap()
-                .filter_map(|x| x.ok())
-                .map(|x| parse_desktop_entry(x.path()))
-                .map(|x| (x.name.clone(), x))
-                .filter(|x| &x.1.name != "")
+            find_desktop_files(&path)
+                .into_iter()
+                .filter_map(|x| Some((desktop_file_id(&path, &x)?, x)))
+                .collect::<Vec<(String, PathBuf)>>()
         })
+        .for_each(|(id, path)| {
+            applications.entry(id.clone()).or_insert_with(|| {
+                let mut app = parse_desktop_entry(path);
+                app.actions.iter_mut().for_each(|x| x.id = id.clone());
+                app.id = id;
+                app
+            });
+        });
+    applications
+        .into_iter()
+        .filter(|x| !x.1.name.is_empty() && x.1.visible)
         .collect::<HashMap<String, Application>>()
 }
 
+/// look up an application by its desktop file id, the `.desktop` suffix being optional
+pub fn find_application(id: &str) -> Result<Application, LauncherError> {
+    let id = if id.ends_with(".desktop") {
+        id.to_string()
+    } else {
+        format!("{}.desktop", id)
+    };
+    generate_application_list()
+        .remove(&id)
+        .ok_or_else(|| LauncherError::LaunchError(format!("no application with the id {}", id)))
+}
+
 #[cfg(test)]
 mod tests {
-    use crate::application::{filter_exec, generate_application_list};
+    use crate::application::{
+        desktop_file_id, filter_exec, find_desktop_files, generate_application_list, is_shown_in,
+        locale_variants, localized_value, parse_desktop_entry, split_list,
+    };
+    use crate::error::LauncherError;
+    use crate::searchable_list::Application;
+    use crate::test_dir::TempDir;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    fn filter(exec: &str, icon: &str, name: &str, uri: &str) -> Result<Vec<String>, LauncherError> {
+        filter_exec(exec.into(), icon.into(), name.into(), uri.into())
+    }
 
     #[test]
     fn it_filters_exec() {
+        assert_eq!(filter("howdy!", "", "", ""), Ok(vec!["howdy!".into()]));
+        assert_eq!(filter("howdy!%F", "", "", ""), Ok(vec!["howdy!".into()]));
+        assert_eq!(filter("ho%fwdy!", "", "", ""), Ok(vec!["howdy!".into()]));
+        assert_eq!(filter("%f%F%u%U%d%D%n%N%v%m", "", "", ""), Ok(vec![]));
         assert_eq!(
-            filter_exec("howdy!".into(), "".into(), "".into(), "".into()),
-            "howdy!"
+            filter("%i", "application", "", ""),
+            Ok(vec!["--icon".into(), "application".into()])
         );
+        assert_eq!(filter("%c", "", "Files", ""), Ok(vec!["Files".into()]));
         assert_eq!(
-            filter_exec("howdy!%F".into(), "".into(), "".into(), "".into()),
-            "howdy!"
+            filter("%k", "", "", "/usr/share/applications/firefox.desktop"),
+            Ok(vec!["/usr/share/applications/firefox.desktop".into()])
         );
+    }
+
+    #[test]
+    fn it_splits_exec_into_arguments() {
+        let cases: Vec<(&str, Result<Vec<&str>, LauncherError>)> = vec![
+            ("", Ok(vec![])),
+            ("firefox %u", Ok(vec!["firefox"])),
+            (
+                "firefox  --new-window\t%U",
+                Ok(vec!["firefox", "--new-window"]),
+            ),
+            ("vim -- %F", Ok(vec!["vim", "--"])),
+            ("app %i --x", Ok(vec!["app", "--icon", "icon-name", "--x"])),
+            ("app --class=%i", Ok(vec!["app", "--class=icon-name"])),
+            ("app %c", Ok(vec!["app", "App Name"])),
+            ("app \"%c\"", Ok(vec!["app", "App Name"])),
+            ("app %k", Ok(vec!["app", "/apps/app.desktop"])),
+            ("app 100%%", Ok(vec!["app", "100%"])),
+            ("app \"\"", Ok(vec!["app", ""])),
+            (
+                "\"/opt/my app/bin\" --flag",
+                Ok(vec!["/opt/my app/bin", "--flag"]),
+            ),
+            ("app \"a b\"c d", Ok(vec!["app", "a bc", "d"])),
+            // `\\` in the file is a string escape for a single backslash, which then quotes the next character
+            (
+                "sh -c \"echo \\\\\"hi\\\\\" \\\\$HOME\"",
+                Ok(vec!["sh", "-c", "echo \"hi\" $HOME"]),
+            ),
+            ("app \"back\\\\\\\\slash\"", Ok(vec!["app", "back\\slash"])),
+            ("app \"a\\sb\"", Ok(vec!["app", "a b"])),
+            ("app; rm -rf ~", Ok(vec!["app;", "rm", "-rf", "~"])),
+            ("app $(whoami) `id`", Ok(vec!["app", "$(whoami)", "`id`"])),
+            ("app %x", Err(LauncherError::ParseExecError)),
+            ("app %", Err(LauncherError::ParseExecError)),
+            ("app \"unterminated", Err(LauncherError::ParseExecError)),
+        ];
+        for (exec, expected) in cases {
+            assert_eq!(
+                filter(exec, "icon-name", "App Name", "/apps/app.desktop"),
+                expected.map(|x| x.into_iter().map(String::from).collect::<Vec<String>>()),
+                "parsing {}",
+                exec
+            );
+        }
+        assert_eq!(filter("app %i", "", "", ""), Ok(vec!["app".into()]));
+    }
+
+    #[test]
+    fn it_splits_lists() {
+        assert_eq!(split_list(""), Vec::<String>::new());
+        assert_eq!(split_list("GNOME;"), vec!["GNOME"]);
+        assert_eq!(split_list("GNOME;KDE"), vec!["GNOME", "KDE"]);
+    }
+
+    #[test]
+    fn it_filters_by_desktop() {
+        let list = |x: &[&str]| x.iter().map(|y| y.to_string()).collect::<Vec<String>>();
+        assert!(is_shown_in(&list(&[]), &list(&[]), &list(&[])));
+        assert!(is_shown_in(&list(&[]), &list(&[]), &list(&["i3"])));
+        assert!(is_shown_in(
+            &list(&["KDE", "i3"]),
+            &list(&[]),
+            &list(&["i3"])
+        ));
+        assert!(!is_shown_in(&list(&["KDE"]), &list(&[]), &list(&["i3"])));
+        assert!(!is_shown_in(&list(&["KDE"]), &list(&[]), &list(&[])));
+        assert!(!is_shown_in(
+            &list(&[]),
+            &list(&["GNOME"]),
+            &list(&["ubuntu", "GNOME"])
+        ));
+        assert!(is_shown_in(&list(&[]), &list(&["GNOME"]), &list(&["KDE"])));
+    }
+
+    #[test]
+    fn it_computes_desktop_file_ids() {
+        let base = Path::new("/usr/share/applications");
         assert_eq!(
-            filter_exec("ho%fwdy!".into(), "".into(), "".into(), "".into()),
-            "howdy!"
+            desktop_file_id(base, &base.join("firefox.desktop")),
+            Some("firefox.desktop".into())
         );
         assert_eq!(
-            filter_exec(
-                "%f%F%u%U%d%D%n%N%v%m".into(),
-                "".into(),
-                "".into(),
-                "".into()
-            ),
-            ""
+            desktop_file_id(base, &base.join("kde/foo.desktop")),
+            Some("kde-foo.desktop".into())
+        );
+        assert_eq!(desktop_file_id(base, Path::new("/opt/foo.desktop")), None);
+    }
+
+    #[test]
+    fn it_finds_desktop_files_recursively() {
+        let base = TempDir::new("find");
+        fs::create_dir_all(base.join("kde")).unwrap();
+        fs::write(base.join("firefox.desktop"), "").unwrap();
+        fs::write(base.join("kde/foo.desktop"), "").unwrap();
+        fs::write(base.join("mimeinfo.cache"), "").unwrap();
+
+        let mut found = find_desktop_files(base.path());
+        found.sort();
+        assert_eq!(
+            found,
+            vec![base.join("firefox.desktop"), base.join("kde/foo.desktop")]
         );
+        assert!(find_desktop_files(&base.join("missing")).is_empty());
+    }
+
+    #[test]
+    fn it_skips_malformed_entries() {
+        let base = TempDir::new("malformed");
+        fs::write(base.join("broken.desktop"), "[Desktop Entry\nName=Broken").unwrap();
+
+        assert_eq!(parse_desktop_entry(base.join("broken.desktop")).name, "");
+        assert_eq!(parse_desktop_entry(base.join("missing.desktop")).name, "");
+    }
+
+    #[test]
+    fn it_computes_locale_variants() {
+        assert_eq!(locale_variants("C"), Vec::<String>::new());
+        assert_eq!(locale_variants("de"), vec!["de"]);
+        assert_eq!(locale_variants("de_DE.UTF-8"), vec!["de_DE", "de"]);
         assert_eq!(
-            filter_exec("%i".into(), "application".into(), "".into(), "".into()),
-            "application"
+            locale_variants("sr_YU.UTF-8@Latn"),
+            vec!["sr_YU@Latn", "sr_YU", "sr@Latn", "sr"]
         );
+        assert_eq!(locale_variants("ja@modifier"), vec!["ja@modifier", "ja"]);
+    }
+
+    #[test]
+    fn it_looks_up_localized_values() {
+        let attributes = vec![
+            ("Name", "Files"),
+            ("Name[de]", "Dateien"),
+            ("Name[ja_JP]", "ファイル"),
+        ]
+        .into_iter()
+        .map(|(x, y)| (x.to_string(), y.to_string()))
+        .collect::<HashMap<String, String>>();
+
+        let lookup = |locale| {
+            localized_value(&attributes, "Name", &locale_variants(locale)).map(String::as_str)
+        };
+        assert_eq!(lookup("de_AT.UTF-8"), Some("Dateien"));
+        assert_eq!(lookup("ja_JP.UTF-8"), Some("ファイル"));
+        assert_eq!(lookup("ja"), Some("Files"));
+        assert_eq!(lookup("C"), Some("Files"));
         assert_eq!(
-            filter_exec("%c".into(), "".into(), "Files".into(), "".into()),
-            "Files"
+            localized_value(&attributes, "Comment", &locale_variants("de")),
+            None
         );
+    }
+
+    #[test]
+    fn it_parses_desktop_actions() {
+        let base = TempDir::new("actions");
+        fs::write(
+            base.join("firefox.desktop"),
+            "[Desktop Entry]\n\
+             Name=Firefox\n\
+             Icon=firefox\n\
+             Exec=firefox %u\n\
+             DBusActivatable=true\n\
+             StartupNotify=true\n\
+             StartupWMClass=firefox\n\
+             Actions=new-window;new-private-window;\n\
+             \n\
+             [Desktop Action new-window]\n\
+             Name=New Window\n\
+             Exec=firefox --new-window %u\n\
+             \n\
+             [Desktop Action new-private-window]\n\
+             Name=New Private Window\n\
+             Icon=firefox-private\n\
+             Exec=firefox --private-window %u\n\
+             \n\
+             [Desktop Action unlisted]\n\
+             Name=Unlisted\n\
+             Exec=firefox --unlisted\n",
+        )
+        .unwrap();
+
+        let app = parse_desktop_entry(base.join("firefox.desktop"));
+        let actions = app
+            .actions
+            .iter()
+            .map(|x| (x.name.as_str(), x.icon.as_str(), x.action.as_str()))
+            .collect::<Vec<(&str, &str, &str)>>();
         assert_eq!(
-            filter_exec(
-                "%k".into(),
-                "".into(),
-                "".into(),
-                "/usr/share/applications/firefox.desktop".into()
-            ),
-            "/usr/share/applications/firefox.desktop"
+            actions,
+            vec![
+                ("Firefox: New Window", "firefox", "new-window"),
+                (
+                    "Firefox: New Private Window",
+                    "firefox-private",
+                    "new-private-window"
+                ),
+            ]
         );
+        assert!(app.actions.iter().all(|x: &Application| x.visible));
+        assert!(app.dbus_activatable);
+        assert!(app.actions.iter().all(|x: &Application| x.dbus_activatable));
+        assert!(app
+            .actions
+            .iter()
+            .all(|x: &Application| x.startup_notify && x.startup_wm_class == "firefox"));
+    }
+
+    #[test]
+    fn it_hides_entries_without_try_exec() {
+        let base = TempDir::new("tryexec");
+        fs::write(
+            base.join("installed.desktop"),
+            "[Desktop Entry]\nName=Installed\nExec=sh\nTryExec=/bin/sh\nPath=/tmp\n",
+        )
+        .unwrap();
+        fs::write(
+            base.join("removed.desktop"),
+            "[Desktop Entry]\nName=Removed\nExec=removed\nTryExec=sky-menu-removed-binary\n",
+        )
+        .unwrap();
+
+        let installed = parse_desktop_entry(base.join("installed.desktop"));
+        assert!(installed.visible);
+        assert_eq!(installed.working_dir, "/tmp");
+        assert!(!parse_desktop_entry(base.join("removed.desktop")).visible);
     }
 
     // #[test]
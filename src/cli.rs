@@ -0,0 +1,251 @@
+use crate::application::{find_application, generate_application_list};
+use crate::config::get_settings;
+use crate::control_socket;
+use crate::control_socket::{parse_mode, Command, InstanceLock};
+use crate::error::LauncherError;
+use crate::keyboard_listener;
+use crate::searchable_list::{Application, ListType};
+use itertools::Itertools;
+use serde_json::json;
+
+pub const USAGE: &str = "usage: sky-menu [subcommand]
+
+  daemon [--replace]          run the launcher, the default without a subcommand
+                              --replace quits an instance which is already running first
+  toggle [--mode <mode>]      open the running launcher in a mode, or hide it if it's open
+  show [--mode <mode>]        open the running launcher until `hide`
+  hide                        hide the running launcher
+  search <text>               open the running launcher with a query typed in
+  reload                      make the running launcher reread its config and applications
+  list-apps [--json]          print the applications which can be launched
+  launch <desktop-id>         launch an application without going through the launcher
+  check                       check whether hotkeys can be read, also `--check`
+
+modes are launcher (the default) and switcher";
+
+/// what to do, as given on the command line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Subcommand {
+    Daemon {
+        replace: bool,
+    },
+    Help,
+    Check,
+    /// pass a command on to the running instance
+    Send(Command),
+    ListApps {
+        json: bool,
+    },
+    Launch(String),
+}
+
+/// `--mode <mode>` or `--mode=<mode>`, the launcher if there isn't one
+fn parse_mode_flag(args: &[&str]) -> Result<ListType, LauncherError> {
+    match args {
+        [] => Ok(ListType::Launcher),
+        ["--mode", x] => parse_mode(x),
+        [x] if x.starts_with("--mode=") => parse_mode(&x["--mode=".len()..]),
+        x => Err(LauncherError::ParseCommandError(format!(
+            "unexpected arguments {}",
+            x.join(" ")
+        ))),
+    }
+}
+
+/// work out the subcommand from the arguments, leaving out the program name
+pub fn parse(args: &[String]) -> Result<Subcommand, LauncherError> {
+    let args = args.iter().map(|x| x.as_str()).collect::<Vec<&str>>();
+    let (name, rest) = args
+        .split_first()
+        .map_or(("daemon", &[][..]), |(x, y)| (*x, y));
+    match (name, rest) {
+        ("daemon", []) => Ok(Subcommand::Daemon { replace: false }),
+        ("daemon", ["--replace"]) | ("--replace", []) => Ok(Subcommand::Daemon { replace: true }),
+        ("help", []) | ("--help", []) | ("-h", []) => Ok(Subcommand::Help),
+        ("check", []) | ("--check", []) => Ok(Subcommand::Check),
+        ("toggle", x) => parse_mode_flag(x).map(|x| Subcommand::Send(Command::Toggle(x))),
+        ("show", x) => parse_mode_flag(x).map(|x| Subcommand::Send(Command::Show(x))),
+        ("hide", []) => Ok(Subcommand::Send(Command::Hide)),
+        ("search", x) if !x.is_empty() => Ok(Subcommand::Send(Command::Search(x.join(" ")))),
+        ("reload", []) => Ok(Subcommand::Send(Command::Reload)),
+        ("list-apps", []) => Ok(Subcommand::ListApps { json: false }),
+        ("list-apps", ["--json"]) => Ok(Subcommand::ListApps { json: true }),
+        ("launch", [x]) => Ok(Subcommand::Launch(x.to_string())),
+        _ => Err(LauncherError::ParseCommandError(format!(
+            "unknown subcommand \"{}\"",
+            args.join(" ")
+        ))),
+    }
+}
+
+/// run anything but the daemon, returning the exit code
+pub fn run(subcommand: Subcommand) -> i32 {
+    let result = match subcommand {
+        Subcommand::Daemon { .. } => unreachable!("the daemon needs qt"),
+        Subcommand::Help => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        Subcommand::Check => check(),
+        Subcommand::Send(x) => control_socket::send(&x),
+        Subcommand::ListApps { json } => {
+            println!("{}", list_apps(json));
+            Ok(())
+        }
+        Subcommand::Launch(x) => launch(&x),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+/// make sure this is the only instance before starting the daemon
+/// returns the lock to hold for as long as it runs, or an exit code if it shouldn't start
+pub fn single_instance(replace: bool) -> Result<Option<InstanceLock>, i32> {
+    match control_socket::lock() {
+        Ok(Some(x)) => return Ok(Some(x)),
+        Ok(None) => {}
+        Err(e) => {
+            // without a runtime directory there's nowhere to put the socket either
+            eprintln!("{}, not checking for another instance", e);
+            return Ok(None);
+        }
+    }
+    if replace {
+        return match control_socket::replace() {
+            Ok(x) => Ok(Some(x)),
+            Err(e) => {
+                eprintln!("Couldn't take over from the running instance: {}", e);
+                Err(1)
+            }
+        };
+    }
+    eprintln!(
+        "sky-menu is already running, opening it instead (start with --replace to take over)"
+    );
+    Err(run(Subcommand::Send(Command::Mode(ListType::Launcher))))
+}
+
+/// report whether hotkeys can work without starting the UI
+fn check() -> Result<(), LauncherError> {
+    // anything wrong with the config gets logged while loading it
+    get_settings();
+    keyboard_listener::check_access()?;
+    println!("Keyboard input is readable");
+    Ok(())
+}
+
+/// one `id<tab>name` line per application, or a json array with a bit more detail for scripts
+fn list_apps(json: bool) -> String {
+    let apps = generate_application_list()
+        .into_values()
+        .sorted_by(|a, b| a.id.cmp(&b.id))
+        .collect::<Vec<Application>>();
+    if !json {
+        return apps
+            .iter()
+            .map(|x| format!("{}\t{}", x.id, x.name))
+            .join("\n");
+    }
+    json!(apps
+        .iter()
+        .map(|x| json!({
+            "id": x.id,
+            "name": x.name,
+            "generic_name": x.generic_name,
+            "comment": x.comment,
+            "icon": x.icon,
+            "exec": x.exec,
+            "terminal": x.terminal,
+            "categories": x.categories,
+            "keywords": x.keywords,
+            "actions": x.actions.iter().map(|y| json!({
+                "action": y.action,
+                "name": y.name,
+            })).collect::<Vec<_>>(),
+        }))
+        .collect::<Vec<_>>())
+    .to_string()
+}
+
+fn launch(id: &str) -> Result<(), LauncherError> {
+    find_application(id)?.try_exec()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::{parse, Subcommand};
+    use crate::control_socket::Command;
+    use crate::searchable_list::ListType;
+
+    fn parse_args(args: &str) -> Option<Subcommand> {
+        parse(
+            &args
+                .split_whitespace()
+                .map(String::from)
+                .collect::<Vec<String>>(),
+        )
+        .ok()
+    }
+
+    #[test]
+    fn it_parses_subcommands() {
+        assert_eq!(parse_args(""), Some(Subcommand::Daemon { replace: false }));
+        assert_eq!(
+            parse_args("daemon"),
+            Some(Subcommand::Daemon { replace: false })
+        );
+        assert_eq!(
+            parse_args("daemon --replace"),
+            Some(Subcommand::Daemon { replace: true })
+        );
+        assert_eq!(
+            parse_args("--replace"),
+            Some(Subcommand::Daemon { replace: true })
+        );
+        assert_eq!(parse_args("--check"), Some(Subcommand::Check));
+        assert_eq!(
+            parse_args("toggle"),
+            Some(Subcommand::Send(Command::Toggle(ListType::Launcher)))
+        );
+        assert_eq!(
+            parse_args("show --mode switcher"),
+            Some(Subcommand::Send(Command::Show(ListType::Switcher)))
+        );
+        assert_eq!(
+            parse_args("show --mode=switcher"),
+            Some(Subcommand::Send(Command::Show(ListType::Switcher)))
+        );
+        assert_eq!(
+            parse_args("search fire fox"),
+            Some(Subcommand::Send(Command::Search("fire fox".into())))
+        );
+        assert_eq!(
+            parse_args("reload"),
+            Some(Subcommand::Send(Command::Reload))
+        );
+        assert_eq!(
+            parse_args("list-apps --json"),
+            Some(Subcommand::ListApps { json: true })
+        );
+        assert_eq!(
+            parse_args("launch firefox.desktop"),
+            Some(Subcommand::Launch("firefox.desktop".into()))
+        );
+        for x in &[
+            "frobnicate",
+            "daemon now",
+            "show --mode",
+            "show --mode windows",
+            "search",
+            "launch",
+            "list-apps --xml",
+        ] {
+            assert_eq!(parse_args(x), None, "{}", x);
+        }
+    }
+}
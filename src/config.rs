@@ -12,13 +12,19 @@ use qmetaobject::*;
 use log::{error, warn};
 use std::collections::HashMap;
 
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use std::path::{PathBuf};
-use std::{fs};
+use std::path::{Path, PathBuf};
+use std::{fs, io};
 
 
 
 
+use crate::hotkey::{Action, Hotkey};
+use crate::inotify_listener;
+use crate::launch::LaunchStrategy;
+use crate::searchable_list::ListType;
+use cached::proc_macro::cached;
 use directories::ProjectDirs;
 use itertools::Itertools;
 
@@ -27,12 +33,331 @@ pub static QUALIFIER: &str = "com.teddyheinen";
 pub static ORGANIZATION: &str = "Teddy Heinen";
 pub static APPLICATION: &str = "sky-menu";
 
+/// settings read from config.toml in the config directory, every section and key is optional
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub window: WindowSettings,
+    pub font: FontSettings,
+    pub search: SearchSettings,
+    /// action name (e.g. `toggle-launcher`) to the key combination which triggers it
+    pub hotkeys: HashMap<String, String>,
+    /// which lists can be opened at all
+    pub modes: Vec<ListType>,
+    pub launch: LaunchSettings,
+    pub keyboard: KeyboardSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct WindowSettings {
+    pub width: i32,
+    pub height: i32,
+    /// top left corner of the window, centered on the screen if unset
+    pub x: Option<i32>,
+    pub y: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FontSettings {
+    pub family: String,
+    pub size: i32,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SearchSettings {
+    /// how many results are shown
+    pub results: usize,
+    pub weights: Weights,
+}
+
+/// relative weights (out of 10) given to a fuzzy match on each field of an application
+/// plus the bonus per past launch and the cap on that bonus
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct Weights {
+    pub name: i64,
+    pub generic_name: i64,
+    pub keyword: i64,
+    pub category: i64,
+    pub comment: i64,
+    pub usage: i64,
+    pub usage_max: i64,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LaunchSettings {
+    pub strategy: LaunchStrategy,
+    /// what Terminal=true applications run in, with `{}` where the command goes or it's appended
+    /// e.g. `["alacritty", "--class", "float", "-e"]`, left empty to find a terminal automatically
+    pub terminal: Vec<String>,
+}
+
+/// with `xkb` set hotkeys match on the symbols of the configured layout rather than on physical keys
+/// the rest are xkbcommon RMLVO names, left empty to use the system defaults
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct KeyboardSettings {
+    /// read hotkeys straight from /dev/input, which needs to be turned off without access to it
+    pub libinput: bool,
+    pub xkb: bool,
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: String,
+    /// milliseconds allowed between the steps of a hotkey sequence
+    pub sequence_timeout: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            window: WindowSettings::default(),
+            font: FontSettings::default(),
+            search: SearchSettings::default(),
+            hotkeys: default_hotkeys(),
+            modes: vec![ListType::Launcher, ListType::Switcher],
+            launch: LaunchSettings::default(),
+            keyboard: KeyboardSettings::default(),
+        }
+    }
+}
+
+impl Default for WindowSettings {
+    fn default() -> Self {
+        WindowSettings {
+            width: 400,
+            height: 500,
+            x: None,
+            y: None,
+        }
+    }
+}
+
+impl Default for FontSettings {
+    fn default() -> Self {
+        FontSettings {
+            family: "Iosevka Aile".into(),
+            size: 16,
+        }
+    }
+}
+
+impl Default for SearchSettings {
+    fn default() -> Self {
+        SearchSettings {
+            results: 9,
+            weights: Weights::default(),
+        }
+    }
+}
+
+impl Default for KeyboardSettings {
+    fn default() -> Self {
+        KeyboardSettings {
+            libinput: true,
+            xkb: false,
+            rules: String::new(),
+            model: String::new(),
+            layout: String::new(),
+            variant: String::new(),
+            options: String::new(),
+            sequence_timeout: 1000,
+        }
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Weights {
+            name: 10,
+            generic_name: 8,
+            keyword: 7,
+            category: 6,
+            comment: 4,
+            usage: 5,
+            usage_max: 50,
+        }
+    }
+}
+
+impl Settings {
+    /// the hotkeys from the config along with the actions they are bound to
+    pub fn bindings(&self) -> Vec<(Action, Hotkey)> {
+        self.hotkeys
+            .iter()
+            .filter_map(|(action, keys)| Some((action.parse().ok()?, keys.parse().ok()?)))
+            .collect()
+    }
+
+    /// put back the defaults for any values which are out of range, logging each one
+    fn validated(mut self) -> Self {
+        let defaults = Settings::default();
+        if self.window.width <= 0 || self.window.height <= 0 {
+            error!(
+                "Invalid window size {}x{} in config file, using {}x{}",
+                self.window.width,
+                self.window.height,
+                defaults.window.width,
+                defaults.window.height
+            );
+            self.window.width = defaults.window.width;
+            self.window.height = defaults.window.height;
+        }
+        if self.window.x.is_some() != self.window.y.is_some() {
+            error!(
+                "Config file sets only one of window.x and window.y, centering the window instead"
+            );
+            self.window.x = None;
+            self.window.y = None;
+        }
+        if self.font.family.trim().is_empty() {
+            error!(
+                "Empty font.family in config file, using {}",
+                defaults.font.family
+            );
+            self.font.family = defaults.font.family;
+        }
+        if self.font.size <= 0 {
+            error!(
+                "Invalid font.size {} in config file, using {}",
+                self.font.size, defaults.font.size
+            );
+            self.font.size = defaults.font.size;
+        }
+        if self.search.results == 0 {
+            error!(
+                "search.results has to be at least 1, using {}",
+                defaults.search.results
+            );
+            self.search.results = defaults.search.results;
+        }
+        let weights = &self.search.weights;
+        if [
+            weights.name,
+            weights.generic_name,
+            weights.keyword,
+            weights.category,
+            weights.comment,
+            weights.usage,
+            weights.usage_max,
+        ]
+        .iter()
+        .any(|x| *x < 0)
+        {
+            error!("Negative search.weights in config file, using the default weights");
+            self.search.weights = defaults.search.weights;
+        }
+        if self.keyboard.sequence_timeout == 0 {
+            error!("Zero keyboard.sequence_timeout in config file, using the default timeout");
+            self.keyboard.sequence_timeout = defaults.keyboard.sequence_timeout;
+        }
+        self.hotkeys.retain(|action, keys| {
+            match (action.parse::<Action>(), keys.parse::<Hotkey>()) {
+                (Ok(_), Ok(_)) => true,
+                (Err(e), _) | (_, Err(e)) => {
+                    error!("Ignoring hotkey {} = \"{}\": {}", action, keys, e);
+                    false
+                }
+            }
+        });
+        if self.modes.is_empty() {
+            error!("No modes enabled in config file, enabling all of them");
+            self.modes = defaults.modes;
+        }
+        self.modes = self.modes.into_iter().unique().collect();
+        self
+    }
+}
+
+fn default_hotkeys() -> HashMap<String, String> {
+    vec![
+        ("toggle-launcher", "Ctrl+Space"),
+        ("show-switcher", "Alt+Tab"),
+    ]
+    .into_iter()
+    .map(|(action, keys)| (action.to_string(), keys.to_string()))
+    .collect()
+}
+
+/// read one section (or top level key) of the config file, `None` if it is missing or invalid
+fn section<T: DeserializeOwned>(table: &toml::value::Table, key: &str, path: &Path) -> Option<T> {
+    table
+        .get(key)?
+        .clone()
+        .try_into()
+        .map_err(|e| {
+            error!(
+                "Invalid {} in config file {}, using the defaults for it: {}",
+                key,
+                path.display(),
+                e
+            )
+        })
+        .ok()
+}
+
+/// read settings from a toml file, falling back on the defaults if it is missing or unparseable
+/// and on the defaults for any section which is invalid
+pub fn load_settings(path: &Path) -> Settings {
+    let text = match fs::read_to_string(path) {
+        Ok(x) => x,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Settings::default(),
+        Err(e) => {
+            error!("Couldn't read config file {}: {}", path.display(), e);
+            return Settings::default();
+        }
+    };
+    let table = match toml::from_str::<toml::value::Table>(&text) {
+        Ok(x) => x,
+        Err(e) => {
+            error!(
+                "Invalid config file {}, using the defaults: {}",
+                path.display(),
+                e
+            );
+            return Settings::default();
+        }
+    };
+    let defaults = Settings::default();
+    Settings {
+        window: section(&table, "window", path).unwrap_or(defaults.window),
+        font: section(&table, "font", path).unwrap_or(defaults.font),
+        search: section(&table, "search", path).unwrap_or(defaults.search),
+        hotkeys: section(&table, "hotkeys", path).unwrap_or(defaults.hotkeys),
+        modes: section(&table, "modes", path).unwrap_or(defaults.modes),
+        launch: section(&table, "launch", path).unwrap_or(defaults.launch),
+        keyboard: section(&table, "keyboard", path).unwrap_or(defaults.keyboard),
+    }
+    .validated()
+}
+
+/// load config.toml from the config directory
+#[cached]
+pub fn get_settings() -> Settings {
+    match ProjectDirs::from(QUALIFIER, ORGANIZATION, APPLICATION) {
+        Some(x) => load_settings(&x.config_dir().join("config.toml")),
+        None => Settings::default(),
+    }
+}
+
 #[derive(QObject, Default)]
 pub struct Config {
     base: qt_base_class!(trait QObject),
 
     height: qt_property!(i32; NOTIFY config_changed),
     width: qt_property!(i32; NOTIFY config_changed),
+    // x and y only apply if positioned, otherwise the window is centered
+    positioned: qt_property!(bool; NOTIFY config_changed),
+    x: qt_property!(i32; NOTIFY config_changed),
+    y: qt_property!(i32; NOTIFY config_changed),
+    font_family: qt_property!(QString; NOTIFY config_changed),
+    font_size: qt_property!(i32; NOTIFY config_changed),
+    results: qt_property!(i32; NOTIFY config_changed),
 
     setup: qt_method!(fn(&mut self)),
 
@@ -40,10 +365,27 @@ pub struct Config {
 }
 
 impl Config {
-    // TODO make this pull from a file
     fn setup(&mut self) {
-        self.height = 500;
-        self.width = 400;
+        self.load();
+
+        let qpointer = QPointer::from(&*self);
+        inotify_listener::on_config_change(qmetaobject::queued_callback(move |()| {
+            if let Some(qself) = qpointer.as_pinned() {
+                qself.borrow_mut().load();
+            }
+        }));
+    }
+
+    fn load(&mut self) {
+        let settings = get_settings();
+        self.height = settings.window.height;
+        self.width = settings.window.width;
+        self.positioned = settings.window.x.is_some();
+        self.x = settings.window.x.unwrap_or(0);
+        self.y = settings.window.y.unwrap_or(0);
+        self.font_family = settings.font.family.into();
+        self.font_size = settings.font.size;
+        self.results = settings.search.results as i32;
 
         self.config_changed();
     }
@@ -71,6 +413,19 @@ impl UsageCount {
         self.store.get(app).unwrap_or(&0).clone()
     }
 
+    /// launches used to be counted under the name, so those counts are used until `app` has its own
+    pub fn get_or(&self, app: &str, old: &str) -> i32 {
+        self.store
+            .get(app)
+            .or_else(|| self.store.get(old))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    pub fn inc_or(&mut self, app: &str, old: &str) {
+        self.set(app, self.get_or(app, old) + 1)
+    }
+
     pub fn set(&mut self, app: &str, val: i32) {
         self.store.insert(app.to_string(), val);
         let json = match serde_json::to_string(&self.store) {
@@ -93,3 +448,142 @@ impl UsageCount {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{load_settings, Settings, UsageCount};
+    use crate::launch::LaunchStrategy;
+    use crate::searchable_list::ListType;
+    use crate::test_dir::TempDir;
+    use std::fs;
+
+    fn load(name: &str, toml: &str) -> Settings {
+        let dir = TempDir::new(name);
+        fs::write(dir.join("config.toml"), toml).unwrap();
+        load_settings(&dir.join("config.toml"))
+    }
+
+    #[test]
+    fn it_loads_settings() {
+        let settings = load(
+            "settings",
+            "modes = [\"launcher\"]\n\
+             [window]\n\
+             width = 600\n\
+             x = 10\n\
+             y = 20\n\
+             [font]\n\
+             family = \"Noto Sans\"\n\
+             [search]\n\
+             results = 5\n\
+             [search.weights]\n\
+             comment = 0\n\
+             [hotkeys]\n\
+             toggle-launcher = \"Super+Space\"\n\
+             [launch]\n\
+             strategy = \"systemd-scope\"\n\
+             terminal = [\"tmux\", \"new-window\", \"{}\"]\n\
+             [keyboard]\n\
+             libinput = false\n\
+             xkb = true\n\
+             layout = \"us\"\n\
+             variant = \"dvorak\"\n\
+             sequence_timeout = 500\n",
+        );
+        assert_eq!((settings.window.width, settings.window.height), (600, 500));
+        assert_eq!((settings.window.x, settings.window.y), (Some(10), Some(20)));
+        assert_eq!(
+            (settings.font.family.as_str(), settings.font.size),
+            ("Noto Sans", 16)
+        );
+        assert_eq!(settings.search.results, 5);
+        assert_eq!(
+            (
+                settings.search.weights.name,
+                settings.search.weights.comment
+            ),
+            (10, 0)
+        );
+        assert_eq!(settings.hotkeys.len(), 1);
+        assert_eq!(settings.hotkeys["toggle-launcher"], "Super+Space");
+        assert_eq!(settings.modes, vec![ListType::Launcher]);
+        assert_eq!(settings.launch.strategy, LaunchStrategy::SystemdScope);
+        assert_eq!(settings.launch.terminal, vec!["tmux", "new-window", "{}"]);
+        assert!(!settings.keyboard.libinput);
+        assert!(settings.keyboard.xkb);
+        assert_eq!(
+            (
+                settings.keyboard.layout.as_str(),
+                settings.keyboard.variant.as_str(),
+                settings.keyboard.rules.as_str()
+            ),
+            ("us", "dvorak", "")
+        );
+        assert_eq!(settings.keyboard.sequence_timeout, 500);
+
+        let missing = load_settings(&TempDir::new("missing").join("config.toml"));
+        assert_eq!(missing.window.width, 400);
+        assert_eq!(missing.modes, vec![ListType::Launcher, ListType::Switcher]);
+        assert_eq!(missing.hotkeys["show-switcher"], "Alt+Tab");
+        assert!(missing.keyboard.libinput);
+        assert!(!missing.keyboard.xkb);
+    }
+
+    #[test]
+    fn it_replaces_invalid_settings() {
+        let settings = load(
+            "invalid",
+            "modes = []\n\
+             [window]\n\
+             width = -5\n\
+             x = 10\n\
+             [font]\n\
+             size = 0\n\
+             [search]\n\
+             results = 0\n\
+             [search.weights]\n\
+             name = -1\n\
+             [hotkeys]\n\
+             toggle-launcher = \"Ctrl+Nope\"\n\
+             show-switcher = \"Alt+Tab\"\n\
+             launch-rockets = \"F1\"\n\
+             [keyboard]\n\
+             sequence_timeout = 0\n",
+        );
+        assert_eq!((settings.window.width, settings.window.height), (400, 500));
+        assert_eq!((settings.window.x, settings.window.y), (None, None));
+        assert_eq!(settings.font.size, 16);
+        assert_eq!(settings.search.results, 9);
+        assert_eq!(settings.search.weights.name, 10);
+        assert_eq!(settings.modes, vec![ListType::Launcher, ListType::Switcher]);
+        assert_eq!(
+            settings.hotkeys.keys().collect::<Vec<_>>(),
+            vec!["show-switcher"]
+        );
+        assert_eq!(settings.keyboard.sequence_timeout, 1000);
+
+        // values of the wrong type throw out their section, but not the rest of the file
+        let settings = load(
+            "mistyped",
+            "[window]\nwidth = \"wide\"\nheight = 100\n[font]\nsize = 20\n",
+        );
+        assert_eq!((settings.window.width, settings.window.height), (400, 500));
+        assert_eq!(settings.font.size, 20);
+        let settings = load("unparseable", "[window\n");
+        assert_eq!(settings.window.width, 400);
+    }
+
+    #[test]
+    fn it_keeps_usage_counted_by_name() {
+        let dir = TempDir::new("usage");
+        fs::write(
+            dir.join("usage.json"),
+            r#"{"Firefox": 4, "Files": 2, "org.gnome.Nautilus.desktop": 7}"#,
+        )
+        .unwrap();
+        let usage = UsageCount::from(dir.join("usage.json"));
+        assert_eq!(usage.get_or("firefox.desktop", "Firefox"), 4);
+        assert_eq!(usage.get_or("org.gnome.Nautilus.desktop", "Files"), 7);
+        assert_eq!(usage.get_or("vim.desktop", "Vim"), 0);
+    }
+}
@@ -0,0 +1,380 @@
+use crate::config;
+use crate::error::LauncherError;
+use crate::searchable_list::ListType;
+use log::{info, warn};
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::io::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use std::{env, fmt, fs, io, thread};
+
+/// something for the running instance to do, sent over the control socket one per line
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    /// `toggle [mode]` - open the list in a mode (the launcher if none is given), or hide it if it's open
+    Toggle(ListType),
+    /// `show <mode>` - open the list until a `hide`, for window manager bindings on both press and release
+    Show(ListType),
+    /// `hide`
+    Hide,
+    /// `search <text>` - open the list with the text already typed in
+    Search(String),
+    /// `mode <mode>` - open the list in a mode until it's dismissed, like the `mode-<name>` hotkeys
+    Mode(ListType),
+    /// `reload` - reread the config and desktop entries
+    Reload,
+    /// `quit` - exit, so a new instance can take over
+    Quit,
+}
+
+pub fn parse_mode(name: &str) -> Result<ListType, LauncherError> {
+    match name {
+        "launcher" => Ok(ListType::Launcher),
+        "switcher" => Ok(ListType::Switcher),
+        "" => Err(LauncherError::ParseCommandError("missing mode".into())),
+        x => Err(LauncherError::ParseCommandError(format!(
+            "unknown mode {}",
+            x
+        ))),
+    }
+}
+
+impl FromStr for Command {
+    type Err = LauncherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (name, argument) = s.split_once(' ').unwrap_or((s, ""));
+        match (name, argument.trim()) {
+            ("toggle", "") => Ok(Command::Toggle(ListType::Launcher)),
+            ("toggle", x) => parse_mode(x).map(Command::Toggle),
+            ("show", x) => parse_mode(x).map(Command::Show),
+            ("hide", "") => Ok(Command::Hide),
+            ("search", x) => Ok(Command::Search(x.to_string())),
+            ("mode", x) => parse_mode(x).map(Command::Mode),
+            ("reload", "") => Ok(Command::Reload),
+            ("quit", "") => Ok(Command::Quit),
+            _ => Err(LauncherError::ParseCommandError(format!(
+                "unknown command \"{}\"",
+                s
+            ))),
+        }
+    }
+}
+
+fn mode_name(mode: ListType) -> &'static str {
+    match mode {
+        ListType::Launcher => "launcher",
+        ListType::Switcher => "switcher",
+    }
+}
+
+/// the line which parses back into the same command
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Command::Toggle(x) => write!(f, "toggle {}", mode_name(*x)),
+            Command::Show(x) => write!(f, "show {}", mode_name(*x)),
+            Command::Hide => write!(f, "hide"),
+            Command::Search(x) => write!(f, "search {}", x),
+            Command::Mode(x) => write!(f, "mode {}", mode_name(*x)),
+            Command::Reload => write!(f, "reload"),
+            Command::Quit => write!(f, "quit"),
+        }
+    }
+}
+
+fn runtime_path(extension: &str) -> Option<PathBuf> {
+    env::var_os("XDG_RUNTIME_DIR")
+        .filter(|x| !x.is_empty())
+        .map(|x| PathBuf::from(x).join(format!("{}.{}", config::APPLICATION, extension)))
+}
+
+/// where the control socket lives, if there is a runtime directory to put it in
+pub fn socket_path() -> Option<PathBuf> {
+    runtime_path("sock")
+}
+
+fn runtime_dir_error() -> LauncherError {
+    LauncherError::ControlSocketError("XDG_RUNTIME_DIR isn't set".into())
+}
+
+// a socket file on its own isn't enough, it's left behind when an instance crashes
+fn is_running(path: &Path) -> bool {
+    UnixStream::connect(path).is_ok()
+}
+
+/// an exclusive lock on a file in the runtime directory, which the daemon holds until it exits
+/// unlike probing the socket it can't be raced, the socket only shows up once qt has started
+pub struct InstanceLock {
+    _file: fs::File,
+}
+
+impl InstanceLock {
+    /// take the lock, `None` if another instance holds it
+    fn try_acquire(path: &Path) -> io::Result<Option<InstanceLock>> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == 0 {
+            return Ok(Some(InstanceLock { _file: file }));
+        }
+        match io::Error::last_os_error() {
+            e if e.raw_os_error() == Some(libc::EWOULDBLOCK) => Ok(None),
+            e => Err(e),
+        }
+    }
+}
+
+fn lock_error(path: &Path, e: io::Error) -> LauncherError {
+    LauncherError::ControlSocketError(format!("{}: {}", path.display(), e))
+}
+
+/// take the instance lock, `None` if another instance is running
+pub fn lock() -> Result<Option<InstanceLock>, LauncherError> {
+    let path = runtime_path("lock").ok_or_else(runtime_dir_error)?;
+    InstanceLock::try_acquire(&path).map_err(|e| lock_error(&path, e))
+}
+
+/// tell the running instance to quit and take the lock once it has
+pub fn replace() -> Result<InstanceLock, LauncherError> {
+    let (socket, lock) = socket_path()
+        .zip(runtime_path("lock"))
+        .ok_or_else(runtime_dir_error)?;
+    replace_at(&socket, &lock, Duration::from_secs(5))
+}
+
+fn replace_at(
+    socket: &Path,
+    lock: &Path,
+    timeout: Duration,
+) -> Result<InstanceLock, LauncherError> {
+    // it can exit before answering, so there's no point checking the response
+    let _ = send_to(socket, &Command::Quit);
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(x) = InstanceLock::try_acquire(lock).map_err(|e| lock_error(lock, e))? {
+            return Ok(x);
+        }
+        if Instant::now() > deadline {
+            return Err(LauncherError::ControlSocketError(
+                "the running instance didn't quit when told to".into(),
+            ));
+        }
+        thread::sleep(Duration::from_millis(50));
+    }
+}
+
+/// send a command to the running instance, failing if nothing is listening or it rejects the command
+pub fn send(command: &Command) -> Result<(), LauncherError> {
+    let path = socket_path().ok_or_else(runtime_dir_error)?;
+    send_to(&path, command)
+}
+
+fn send_to(path: &Path, command: &Command) -> Result<(), LauncherError> {
+    let error =
+        |e: io::Error| LauncherError::ControlSocketError(format!("{}: {}", path.display(), e));
+    let mut stream = UnixStream::connect(path).map_err(error)?;
+    writeln!(stream, "{}", command).map_err(error)?;
+    let mut response = String::new();
+    BufReader::new(stream)
+        .read_line(&mut response)
+        .map_err(error)?;
+    match response.trim_end() {
+        "ok" => Ok(()),
+        x => Err(LauncherError::ControlSocketError(
+            x.trim_start_matches("error: ").to_string(),
+        )),
+    }
+}
+
+/// accept commands on the control socket in the background, calling `handler` with each of them
+pub fn listen(handler: impl Fn(Command) + Send + Sync + Clone + 'static) {
+    match socket_path() {
+        Some(path) => {
+            if let Err(e) = serve(&path, handler) {
+                warn!("Couldn't listen for commands on {}: {}", path.display(), e);
+            }
+        }
+        None => warn!("XDG_RUNTIME_DIR isn't set, not listening for commands"),
+    }
+}
+
+fn serve(path: &Path, handler: impl Fn(Command) + Send + Sync + Clone + 'static) -> io::Result<()> {
+    // a socket left behind by an instance which didn't exit cleanly would stop the bind,
+    // but one which is still answering belongs to someone else
+    if is_running(path) {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            "another instance is listening",
+        ));
+    }
+    if path.exists() {
+        fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("Listening for commands on {}", path.display());
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    let handler = handler.clone();
+                    thread::spawn(move || respond(stream, handler));
+                }
+                Err(e) => warn!("Couldn't accept a connection on the control socket: {}", e),
+            }
+        }
+    });
+    Ok(())
+}
+
+/// run each line sent as a command, answering with `ok` or what was wrong with it
+fn respond(stream: UnixStream, handler: impl Fn(Command)) {
+    let mut writer = match stream.try_clone() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!("Couldn't answer on the control socket: {}", e);
+            return;
+        }
+    };
+    for line in BufReader::new(stream).lines() {
+        let line = match line {
+            Ok(x) => x,
+            Err(_) => return,
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let response = match line.parse::<Command>() {
+            Ok(command) => {
+                handler(command);
+                "ok".to_string()
+            }
+            Err(e) => format!("error: {}", e),
+        };
+        if writeln!(writer, "{}", response).is_err() {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_socket::{is_running, replace_at, send_to, serve, Command, InstanceLock};
+    use crate::searchable_list::ListType;
+    use crate::test_dir::TempDir;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::os::unix::net::UnixStream;
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn it_parses_commands() {
+        assert_eq!(
+            "toggle".parse::<Command>(),
+            Ok(Command::Toggle(ListType::Launcher))
+        );
+        assert_eq!(
+            "toggle switcher\n".parse::<Command>(),
+            Ok(Command::Toggle(ListType::Switcher))
+        );
+        assert_eq!(
+            "show switcher".parse::<Command>(),
+            Ok(Command::Show(ListType::Switcher))
+        );
+        assert_eq!("hide".parse::<Command>(), Ok(Command::Hide));
+        assert_eq!(
+            "search fire fox".parse::<Command>(),
+            Ok(Command::Search("fire fox".into()))
+        );
+        assert_eq!(
+            "mode launcher".parse::<Command>(),
+            Ok(Command::Mode(ListType::Launcher))
+        );
+        for x in &["", "launch", "show", "mode windows", "hide now"] {
+            assert!(x.parse::<Command>().is_err(), "{}", x);
+        }
+
+        for x in &[
+            Command::Toggle(ListType::Switcher),
+            Command::Show(ListType::Launcher),
+            Command::Hide,
+            Command::Search("fire fox".into()),
+            Command::Mode(ListType::Switcher),
+            Command::Reload,
+            Command::Quit,
+        ] {
+            assert_eq!(x.to_string().parse::<Command>().as_ref(), Ok(x));
+        }
+    }
+
+    #[test]
+    fn it_answers_commands() {
+        let dir = TempDir::new("control");
+        let path = dir.join("control.sock");
+        // a stale socket from a previous run is replaced
+        fs::write(&path, "").unwrap();
+        let (commands, received) = mpsc::channel();
+        let commands = Arc::new(Mutex::new(commands));
+        serve(&path, move |x| commands.lock().unwrap().send(x).unwrap()).unwrap();
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        stream
+            .write_all(b"show switcher\nlaunch rockets\n\nhide\n")
+            .unwrap();
+        let responses = BufReader::new(stream)
+            .lines()
+            .take(3)
+            .collect::<Result<Vec<String>, _>>()
+            .unwrap();
+        assert_eq!(responses[0], "ok");
+        assert!(responses[1].starts_with("error: "));
+        assert_eq!(responses[2], "ok");
+        assert_eq!(
+            received.try_iter().collect::<Vec<Command>>(),
+            vec![Command::Show(ListType::Switcher), Command::Hide]
+        );
+
+        assert!(send_to(&path, &Command::Reload).is_ok());
+        assert_eq!(received.try_recv(), Ok(Command::Reload));
+        // as if it had gone away
+        fs::remove_file(&path).unwrap();
+        assert!(send_to(&path, &Command::Reload).is_err());
+    }
+
+    #[test]
+    fn it_keeps_to_one_instance() {
+        let dir = TempDir::new("running");
+        let (socket, lock) = (dir.join("running.sock"), dir.join("running.lock"));
+        assert!(!is_running(&socket));
+        // left behind by a crash
+        fs::write(&socket, "").unwrap();
+        assert!(!is_running(&socket));
+
+        let held = InstanceLock::try_acquire(&lock).unwrap();
+        assert!(held.is_some());
+        assert!(InstanceLock::try_acquire(&lock).unwrap().is_none());
+
+        // this one ignores being told to quit, so it's never replaced
+        let (commands, received) = mpsc::channel();
+        let commands = Arc::new(Mutex::new(commands));
+        serve(&socket, move |x| commands.lock().unwrap().send(x).unwrap()).unwrap();
+        assert!(is_running(&socket));
+        assert!(serve(&socket, |_| {}).is_err());
+        assert!(is_running(&socket));
+        assert!(replace_at(&socket, &lock, Duration::from_millis(100)).is_err());
+        assert_eq!(received.try_recv(), Ok(Command::Quit));
+
+        // as if it had quit
+        drop(held);
+        assert!(replace_at(&socket, &lock, Duration::from_millis(100)).is_ok());
+    }
+}
@@ -0,0 +1,260 @@
+use crate::control_socket::{parse_mode, Command};
+use crate::error::LauncherError;
+use crate::searchable_list::ListType;
+use dbus::blocking::stdintf::org_freedesktop_dbus::RequestNameReply;
+use dbus::blocking::SyncConnection;
+use dbus::channel::{MatchingReceiver, Sender};
+use dbus::message::{MatchRule, Message};
+use dbus::MethodErr;
+use log::{info, warn};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// the well-known name, which is also the interface name
+pub const NAME: &str = "org.sky_menu.Launcher";
+pub const PATH: &str = "/org/sky_menu/Launcher";
+
+const INTROSPECTABLE: &str = "org.freedesktop.DBus.Introspectable";
+
+const INTROSPECTION: &str = r#"<!DOCTYPE node PUBLIC "-//freedesktop//DTD D-BUS Object Introspection 1.0//EN"
+ "http://www.freedesktop.org/standards/dbus/1.0/introspect.dtd">
+<node>
+  <interface name="org.sky_menu.Launcher">
+    <method name="Toggle"/>
+    <method name="Show">
+      <arg name="mode" type="s" direction="in"/>
+    </method>
+    <method name="Hide"/>
+    <method name="Search">
+      <arg name="query" type="s" direction="in"/>
+    </method>
+    <method name="Launch">
+      <arg name="id" type="s" direction="in"/>
+    </method>
+    <signal name="Launched">
+      <arg name="id" type="s"/>
+    </signal>
+    <signal name="VisibilityChanged">
+      <arg name="visible" type="b"/>
+    </signal>
+  </interface>
+  <interface name="org.freedesktop.DBus.Introspectable">
+    <method name="Introspect">
+      <arg name="xml" type="s" direction="out"/>
+    </method>
+  </interface>
+</node>
+"#;
+
+/// the launcher on the session bus, kept around to emit signals as things happen
+#[derive(Clone)]
+pub struct Service {
+    conn: Arc<SyncConnection>,
+}
+
+impl Service {
+    pub fn launched(&self, id: &str) {
+        self.emit(signal("Launched").append1(id));
+    }
+
+    pub fn visibility_changed(&self, visible: bool) {
+        self.emit(signal("VisibilityChanged").append1(visible));
+    }
+
+    // written out by the processing thread, flushing here would wait on it
+    fn emit(&self, signal: Message) {
+        let _ = self.conn.send(signal);
+    }
+}
+
+fn signal(name: &str) -> Message {
+    Message::new_signal(PATH, NAME, name).expect("signal names are valid")
+}
+
+/// offer the launcher on the session bus, `commands` doing the same as over the control socket
+/// and `launch` starting an application by its desktop file id
+pub fn listen(
+    commands: impl Fn(Command) + Send + Sync + 'static,
+    launch: impl Fn(&str) -> Result<(), LauncherError> + Send + Sync + 'static,
+) -> Option<Service> {
+    match SyncConnection::new_session().and_then(|x| serve(x, commands, launch)) {
+        Ok(x) => Some(x),
+        Err(e) => {
+            warn!("Couldn't offer {} on the session bus: {}", NAME, e);
+            None
+        }
+    }
+}
+
+fn serve(
+    conn: SyncConnection,
+    commands: impl Fn(Command) + Send + Sync + 'static,
+    launch: impl Fn(&str) -> Result<(), LauncherError> + Send + Sync + 'static,
+) -> Result<Service, dbus::Error> {
+    match conn.request_name(NAME, false, false, true)? {
+        RequestNameReply::PrimaryOwner | RequestNameReply::AlreadyOwner => {}
+        _ => {
+            return Err(dbus::Error::new_failed(&format!(
+                "{} is owned by another instance",
+                NAME
+            )))
+        }
+    }
+    conn.start_receive(
+        MatchRule::new_method_call(),
+        Box::new(move |msg, conn| {
+            let reply =
+                answer(&msg, conn, &commands, &launch).unwrap_or_else(|e| e.to_message(&msg));
+            let _ = conn.send(reply);
+            true
+        }),
+    );
+    info!("Offering {} on the session bus", NAME);
+
+    let conn = Arc::new(conn);
+    let processing = conn.clone();
+    // a short timeout, as signals emitted from other threads are only sent out between calls
+    thread::spawn(move || loop {
+        if let Err(e) = processing.process(Duration::from_millis(100)) {
+            warn!("Lost the session bus connection: {}", e);
+            return;
+        }
+    });
+    Ok(Service { conn })
+}
+
+fn read_mode(msg: &Message) -> Result<ListType, MethodErr> {
+    let mode: &str = msg.read1()?;
+    parse_mode(mode).map_err(|e| MethodErr::invalid_arg(&e.to_string()))
+}
+
+/// do what a method call asks for, the reply being an error if it can't be done
+fn answer(
+    msg: &Message,
+    conn: &SyncConnection,
+    commands: &impl Fn(Command),
+    launch: &impl Fn(&str) -> Result<(), LauncherError>,
+) -> Result<Message, MethodErr> {
+    let path = msg.path().map(|x| x.to_string()).unwrap_or_default();
+    if path != PATH {
+        return Err(MethodErr::no_path(&path));
+    }
+    let member = msg.member().map(|x| x.to_string()).unwrap_or_default();
+    // the interface is optional in a method call
+    match (msg.interface().as_deref(), member.as_str()) {
+        (Some(INTROSPECTABLE), "Introspect") => {
+            return Ok(msg.method_return().append1(INTROSPECTION))
+        }
+        (Some(NAME), _) | (None, _) => {}
+        (Some(x), _) => return Err(MethodErr::no_interface(x)),
+    }
+    match member.as_str() {
+        "Toggle" => commands(Command::Toggle(ListType::Launcher)),
+        "Show" => commands(Command::Show(read_mode(msg)?)),
+        "Hide" => commands(Command::Hide),
+        "Search" => commands(Command::Search(msg.read1::<&str>()?.to_string())),
+        "Launch" => {
+            let id: &str = msg.read1()?;
+            launch(id).map_err(|e| MethodErr::failed(&e))?;
+            let _ = conn.send(signal("Launched").append1(id));
+        }
+        x => return Err(MethodErr::no_method(x)),
+    }
+    Ok(msg.method_return())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::control_socket::Command;
+    use crate::dbus_service::{serve, NAME, PATH};
+    use crate::error::LauncherError;
+    use crate::searchable_list::ListType;
+    use crate::test_bus::PrivateBus;
+    use dbus::channel::MatchingReceiver;
+    use dbus::message::{MatchRule, MessageType};
+    use std::sync::{mpsc, Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn it_serves_the_launcher() {
+        let bus = PrivateBus::new();
+        let (commands, received) = mpsc::channel();
+        let commands = Arc::new(Mutex::new(commands));
+        let service = serve(
+            bus.connect_sync(),
+            move |x| commands.lock().unwrap().send(x).unwrap(),
+            |id| match id {
+                "firefox.desktop" => Ok(()),
+                x => Err(LauncherError::LaunchError(format!("no application {}", x))),
+            },
+        )
+        .unwrap();
+        // a second instance doesn't get the name
+        assert!(serve(bus.connect_sync(), |_| {}, |_| Ok(())).is_err());
+
+        let client = bus.connect();
+        let (signals, received_signals) = mpsc::channel();
+        let rule = MatchRule::new()
+            .with_type(MessageType::Signal)
+            .with_interface(NAME);
+        client.add_match_no_cb(&rule.match_str()).unwrap();
+        client.start_receive(
+            rule,
+            Box::new(move |msg, _| {
+                let member = msg.member().unwrap().to_string();
+                let argument = match member.as_str() {
+                    "Launched" => msg.read1::<String>().unwrap(),
+                    _ => msg.read1::<bool>().unwrap().to_string(),
+                };
+                signals.send((member, argument)).unwrap();
+                true
+            }),
+        );
+
+        let proxy = client.with_proxy(NAME, PATH, Duration::from_secs(5));
+        let call = |method: &str, argument: Option<&str>| -> Result<(), dbus::Error> {
+            match argument {
+                Some(x) => proxy.method_call(NAME, method, (x,)),
+                None => proxy.method_call(NAME, method, ()),
+            }
+        };
+        call("Toggle", None).unwrap();
+        call("Show", Some("switcher")).unwrap();
+        assert!(call("Show", Some("windows")).is_err());
+        assert!(call("Show", None).is_err());
+        call("Hide", None).unwrap();
+        call("Search", Some("fire fox")).unwrap();
+        assert!(call("Frobnicate", None).is_err());
+        assert_eq!(
+            received.try_iter().collect::<Vec<Command>>(),
+            vec![
+                Command::Toggle(ListType::Launcher),
+                Command::Show(ListType::Switcher),
+                Command::Hide,
+                Command::Search("fire fox".into()),
+            ]
+        );
+
+        call("Launch", Some("firefox.desktop")).unwrap();
+        assert!(call("Launch", Some("missing.desktop")).is_err());
+        service.visibility_changed(true);
+        let (xml,): (String,) = proxy
+            .method_call("org.freedesktop.DBus.Introspectable", "Introspect", ())
+            .unwrap();
+        assert!(xml.contains("<signal name=\"VisibilityChanged\">"));
+
+        // the bus sends a few signals of its own, which don't match
+        let mut signals = vec![];
+        while signals.len() < 2 && client.process(Duration::from_secs(5)).unwrap() {
+            signals.extend(received_signals.try_iter());
+        }
+        assert_eq!(
+            signals,
+            vec![
+                ("Launched".to_string(), "firefox.desktop".to_string()),
+                ("VisibilityChanged".to_string(), "true".to_string()),
+            ]
+        );
+    }
+}
@@ -2,19 +2,36 @@ use core::fmt;
 use std::error::Error;
 
 #[derive(Debug, Clone, Hash, Eq, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum LauncherError {
     ResolveIconThemeError,
+    ParseExecError,
+    LaunchError(String),
+    ParseHotkeyError(String),
+    InputAccessError(String),
+    ParseCommandError(String),
+    ControlSocketError(String),
 }
 
 impl fmt::Display for LauncherError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(
-            f,
-            "{}",
-            match self {
-                LauncherError::ResolveIconThemeError => "Unable to resolve icon theme",
+        match self {
+            LauncherError::ResolveIconThemeError => write!(f, "Unable to resolve icon theme"),
+            LauncherError::ParseExecError => write!(f, "Unable to parse Exec key"),
+            LauncherError::LaunchError(x) => write!(f, "Unable to launch program: {}", x),
+            LauncherError::ParseHotkeyError(x) => write!(f, "Unable to parse hotkey: {}", x),
+            LauncherError::InputAccessError(x) => {
+                write!(
+                    f,
+                    "Unable to read keyboard input, hotkeys won't work: {}",
+                    x
+                )
             }
-        )
+            LauncherError::ParseCommandError(x) => write!(f, "Unable to parse command: {}", x),
+            LauncherError::ControlSocketError(x) => {
+                write!(f, "Unable to reach the running instance: {}", x)
+            }
+        }
     }
 }
 
@@ -0,0 +1,516 @@
+use crate::error::LauncherError;
+use crate::keyboard_listener::KeyEvent;
+use crate::searchable_list::ListType;
+use crate::xkb;
+use keycode::{KeyMap, KeyMapping, KeyMappingCode, KeyMappingId};
+use std::str::FromStr;
+
+/// something a hotkey can be bound to, named in the `[hotkeys]` section of the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// `toggle-launcher`
+    ToggleLauncher,
+    /// `show-switcher` - shown while the modifiers are held, like alt-tab, or until dismissed if nothing is left held
+    ShowSwitcher,
+    /// `mode-<name>` e.g. `mode-switcher` - opened in that mode until dismissed
+    Open(ListType),
+}
+
+impl FromStr for Action {
+    type Err = LauncherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "toggle-launcher" => Ok(Action::ToggleLauncher),
+            "show-switcher" => Ok(Action::ShowSwitcher),
+            "mode-launcher" => Ok(Action::Open(ListType::Launcher)),
+            "mode-switcher" => Ok(Action::Open(ListType::Switcher)),
+            _ => Err(LauncherError::ParseHotkeyError(format!(
+                "unknown action {}",
+                s
+            ))),
+        }
+    }
+}
+
+/// one or more chords pressed one after the other, separated by `;` as in `Ctrl+Space; W`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hotkey {
+    steps: Vec<Chord>,
+}
+
+/// a key combination such as `Super+Space` or `Ctrl+Alt+T`
+/// prefixing the key with `@` (`Super+@Space`) triggers it on release instead,
+/// and modifiers on their own (`Super`) trigger when tapped without any other key
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    modifiers: Vec<Modifier>,
+    /// none for modifiers which are tapped on their own
+    key: Option<Key>,
+    release: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Key {
+    /// evdev code of the key, if it names a physical key
+    code: Option<u16>,
+    /// keysym of the key, used instead when hotkeys are matched through xkbcommon
+    keysym: Option<u32>,
+}
+
+/// evdev codes of a modifier, which counts as held if any of its keys are
+/// so `Ctrl` is either control key while `LCtrl` is only the left one
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Modifier {
+    keys: Vec<u16>,
+    /// xkbcommon name of the modifier
+    name: &'static str,
+}
+
+fn evdev(id: KeyMappingId) -> u16 {
+    KeyMap::from(id).evdev
+}
+
+/// every modifier key, none of which may be held unless the hotkey asks for it
+fn all_modifiers() -> Vec<u16> {
+    [
+        KeyMappingId::ControlLeft,
+        KeyMappingId::ControlRight,
+        KeyMappingId::ShiftLeft,
+        KeyMappingId::ShiftRight,
+        KeyMappingId::AltLeft,
+        KeyMappingId::AltRight,
+        KeyMappingId::MetaLeft,
+        KeyMappingId::MetaRight,
+    ]
+    .iter()
+    .map(|x| evdev(*x))
+    .collect()
+}
+
+/// whether a key is one of the modifiers, which are pressed on the way to the next step of a sequence
+pub fn is_modifier(key: u8) -> bool {
+    all_modifiers().contains(&(key as u16))
+}
+
+fn parse_modifier(name: &str) -> Option<Modifier> {
+    let (ids, name) = match name {
+        "ctrl" | "control" => (
+            vec![KeyMappingId::ControlLeft, KeyMappingId::ControlRight],
+            xkb::CTRL,
+        ),
+        "lctrl" => (vec![KeyMappingId::ControlLeft], xkb::CTRL),
+        "rctrl" => (vec![KeyMappingId::ControlRight], xkb::CTRL),
+        "shift" => (
+            vec![KeyMappingId::ShiftLeft, KeyMappingId::ShiftRight],
+            xkb::SHIFT,
+        ),
+        "lshift" => (vec![KeyMappingId::ShiftLeft], xkb::SHIFT),
+        "rshift" => (vec![KeyMappingId::ShiftRight], xkb::SHIFT),
+        "alt" => (
+            vec![KeyMappingId::AltLeft, KeyMappingId::AltRight],
+            xkb::ALT,
+        ),
+        "lalt" => (vec![KeyMappingId::AltLeft], xkb::ALT),
+        "ralt" | "altgr" => (vec![KeyMappingId::AltRight], xkb::ALT),
+        "super" | "meta" | "logo" | "win" => (
+            vec![KeyMappingId::MetaLeft, KeyMappingId::MetaRight],
+            xkb::LOGO,
+        ),
+        "lsuper" => (vec![KeyMappingId::MetaLeft], xkb::LOGO),
+        "rsuper" => (vec![KeyMappingId::MetaRight], xkb::LOGO),
+        _ => return None,
+    };
+    Some(Modifier {
+        keys: ids.into_iter().map(evdev).collect(),
+        name,
+    })
+}
+
+/// look a key up by its name in the UI Events spec (`Space`, `KeyT`, `F1`, ...), a few common aliases,
+/// or just the letter or digit on it
+fn parse_key(name: &str) -> Option<u16> {
+    let code = match name {
+        "return" => "enter".to_string(),
+        "esc" => "escape".to_string(),
+        "up" | "down" | "left" | "right" => format!("arrow{}", name),
+        "del" => "delete".to_string(),
+        "pgup" => "pageup".to_string(),
+        "pgdown" => "pagedown".to_string(),
+        x if x.len() == 1 && x.chars().all(|y| y.is_ascii_lowercase()) => format!("key{}", x),
+        x if x.len() == 1 && x.chars().all(|y| y.is_ascii_digit()) => format!("digit{}", x),
+        x => x.to_string(),
+    };
+    (1..256).find(|x| {
+        KeyMap::from_key_mapping(KeyMapping::Evdev(*x))
+            .ok()
+            .and_then(|y| y.code)
+            .is_some_and(|y: KeyMappingCode| y.to_string().to_lowercase() == code)
+    })
+}
+
+/// look a key up by its keysym name (`space`, `t`, `ampersand`, ...), accepting the same aliases as `parse_key`
+fn parse_keysym(name: &str) -> Option<u32> {
+    let name = match name {
+        "enter" => "return",
+        "esc" => "escape",
+        "del" => "delete",
+        "pgup" | "pageup" => "prior",
+        "pgdown" | "pagedown" => "next",
+        x => x
+            .strip_prefix("arrow")
+            .or_else(|| x.strip_prefix("key").filter(|y| y.len() == 1))
+            .or_else(|| x.strip_prefix("digit").filter(|y| y.len() == 1))
+            .unwrap_or(x),
+    };
+    xkb::keysym_from_name(name)
+}
+
+impl FromStr for Hotkey {
+    type Err = LauncherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let steps = s
+            .split(';')
+            .map(|x| x.parse::<Chord>())
+            .collect::<Result<Vec<Chord>, LauncherError>>()?;
+        Ok(Hotkey { steps })
+    }
+}
+
+impl FromStr for Chord {
+    type Err = LauncherError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = |x: &str| LauncherError::ParseHotkeyError(format!("{} in \"{}\"", x, s.trim()));
+        let mut names = s
+            .split('+')
+            .map(|x| x.trim().to_lowercase())
+            .collect::<Vec<String>>();
+        let last = names.last_mut().unwrap();
+        let release = last.starts_with('@');
+        *last = last.trim_start_matches('@').trim().to_string();
+        if names.iter().any(|x| x.is_empty()) {
+            return Err(error("empty key name"));
+        }
+        // nothing but modifiers means tapping them
+        if names.iter().all(|x| parse_modifier(x).is_some()) {
+            return Ok(Chord {
+                modifiers: names.iter().filter_map(|x| parse_modifier(x)).collect(),
+                key: None,
+                release: true,
+            });
+        }
+        let (key, modifiers) = names.split_last().unwrap();
+        let modifiers = modifiers
+            .iter()
+            .map(|x| parse_modifier(x).ok_or_else(|| error(&format!("unknown modifier {}", x))))
+            .collect::<Result<Vec<Modifier>, LauncherError>>()?;
+        if parse_modifier(key).is_some() {
+            return Err(error("modifier after the key"));
+        }
+        let (name, code, keysym) = (key, parse_key(key), parse_keysym(key));
+        if code.is_none() && keysym.is_none() {
+            return Err(error(&format!("unknown key {}", name)));
+        }
+        Ok(Chord {
+            modifiers,
+            key: Some(Key { code, keysym }),
+            release,
+        })
+    }
+}
+
+impl Hotkey {
+    /// the chords to press in order
+    pub fn steps(&self) -> Vec<Chord> {
+        self.steps.clone()
+    }
+
+    /// whether the hotkey ends in a combination which is held down, rather than a tap or release
+    pub fn held(&self) -> bool {
+        let last = self.steps.last().unwrap();
+        last.key.is_some() && !last.release && !last.modifiers.is_empty()
+    }
+
+    /// whether releasing a key let go of one of the modifiers of the hotkey's last step
+    pub fn released(&self, event: &KeyEvent) -> bool {
+        let last = self.steps.last().unwrap();
+        if event.pressed {
+            return false;
+        }
+        match &event.xkb {
+            Some(xkb) => last.modifiers.iter().any(|x| {
+                xkb.previous_modifiers.contains(&x.name) && !xkb.modifiers.contains(&x.name)
+            }),
+            None => last.modifiers.iter().any(|x| {
+                x.keys.contains(&(event.key as u16))
+                    && x.keys.iter().all(|y| !event.keys[*y as usize])
+            }),
+        }
+    }
+}
+
+impl Chord {
+    /// whether a key event completed the chord
+    pub fn triggered(&self, event: &KeyEvent) -> bool {
+        match &self.key {
+            Some(key) => event.pressed != self.release && self.matches(key, event),
+            None => self.tapped(event),
+        }
+    }
+
+    /// whether the event was for the chord's key, with exactly its modifiers held
+    fn matches(&self, key: &Key, event: &KeyEvent) -> bool {
+        match &event.xkb {
+            // the keysym in the current layout and whatever modifiers xkb thinks are active,
+            // so remapped modifiers count too, though a modifier naming a side still needs that key held
+            Some(xkb) => {
+                key.keysym
+                    .map_or(key.code == Some(event.key as u16), |x| x == xkb.keysym)
+                    && xkb::MODIFIERS.iter().all(|x| {
+                        xkb.modifiers.contains(x) == self.modifiers.iter().any(|y| y.name == *x)
+                    })
+                    && self
+                        .modifiers
+                        .iter()
+                        .filter(|x| x.keys.len() == 1)
+                        .all(|x| event.keys[x.keys[0] as usize])
+            }
+            None => {
+                key.code == Some(event.key as u16)
+                    && self
+                        .modifiers
+                        .iter()
+                        .all(|x| x.keys.iter().any(|y| event.keys[*y as usize]))
+                    && all_modifiers()
+                        .iter()
+                        .filter(|x| !self.modifiers.iter().any(|y| y.keys.contains(x)))
+                        .all(|x| !event.keys[*x as usize])
+            }
+        }
+    }
+
+    /// whether the last of the modifiers was just let go of, with nothing but them pressed since the keyboard was idle
+    fn tapped(&self, event: &KeyEvent) -> bool {
+        !event.pressed
+            && self
+                .modifiers
+                .iter()
+                .all(|x| x.keys.iter().any(|y| event.struck[*y as usize]))
+            && (0..event.struck.len())
+                .filter(|x| event.struck[*x])
+                .all(|x| self.modifiers.iter().any(|y| y.keys.contains(&(x as u16))))
+            && self
+                .modifiers
+                .iter()
+                .all(|x| x.keys.iter().all(|y| !event.keys[*y as usize]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::KeyboardSettings;
+    use crate::hotkey::{Action, Chord, Hotkey};
+    use crate::keyboard_listener::KeyEvent;
+    use crate::searchable_list::ListType;
+    use crate::xkb::XkbKeyboard;
+
+    const LCTRL: usize = 29;
+    const RCTRL: usize = 97;
+    const LSHIFT: usize = 42;
+    const LALT: usize = 56;
+    const LSUPER: usize = 125;
+    const SPACE: usize = 57;
+    const T: usize = 20;
+    const K: usize = 37;
+
+    fn state(pressed: &[usize]) -> [bool; 256] {
+        let mut state = [false; 256];
+        pressed.iter().for_each(|x| state[*x] = true);
+        state
+    }
+
+    /// `key` being pressed while `held` already are
+    fn press(held: &[usize], key: usize) -> KeyEvent {
+        let mut keys = state(held);
+        keys[key] = true;
+        KeyEvent {
+            key: key as u8,
+            pressed: true,
+            keys,
+            struck: keys,
+            xkb: None,
+        }
+    }
+
+    /// `key` being released while `held` still are
+    fn release(held: &[usize], key: usize) -> KeyEvent {
+        let mut struck = state(held);
+        struck[key] = true;
+        KeyEvent {
+            key: key as u8,
+            pressed: false,
+            keys: state(held),
+            struck,
+            xkb: None,
+        }
+    }
+
+    #[test]
+    fn it_parses_hotkeys() {
+        for x in &[
+            "Super+Space",
+            "Ctrl+Alt+T",
+            "ctrl + alt + t",
+            "LCtrl+Return",
+            "F1",
+            "Alt+Tab",
+            "Shift+Digit1",
+            "Super",
+            "Ctrl+Shift",
+            "Super+@Space",
+            "Ctrl+Space; W",
+        ] {
+            assert!(x.parse::<Hotkey>().is_ok(), "{}", x);
+        }
+        assert_eq!(
+            "Ctrl+Alt+T".parse::<Hotkey>(),
+            "control+ALT+KeyT".parse::<Hotkey>()
+        );
+        assert_eq!(
+            "Ctrl+Space; W".parse::<Hotkey>().unwrap().steps(),
+            vec![
+                "Ctrl+Space".parse::<Chord>().unwrap(),
+                "W".parse::<Chord>().unwrap()
+            ]
+        );
+        for x in &[
+            "",
+            "Ctrl+",
+            "@",
+            "Hyper+A",
+            "Ctrl+NoSuchKey",
+            "Ctrl++",
+            "T+Ctrl",
+            "Ctrl+Space;",
+        ] {
+            assert!(x.parse::<Hotkey>().is_err(), "{}", x);
+        }
+
+        // only a combination which is still held can be let go of
+        for (x, held) in &[
+            ("Alt+Tab", true),
+            ("Super", false),
+            ("Super+@Space", false),
+            ("Ctrl+Space; W", false),
+            ("Ctrl+Space; Alt+W", true),
+        ] {
+            assert_eq!(x.parse::<Hotkey>().unwrap().held(), *held, "{}", x);
+        }
+    }
+
+    #[test]
+    fn it_matches_hotkeys() {
+        let chord = "Ctrl+Alt+T".parse::<Chord>().unwrap();
+        assert!(chord.triggered(&press(&[LCTRL, LALT], T)));
+        // either side counts unless a side is named
+        assert!(chord.triggered(&press(&[RCTRL, LALT], T)));
+        assert!(!"LCtrl+Alt+T"
+            .parse::<Chord>()
+            .unwrap()
+            .triggered(&press(&[RCTRL, LALT], T)));
+        // only when the key itself is pressed, not when modifiers are pressed afterwards
+        assert!(!chord.triggered(&press(&[LCTRL, T], LALT)));
+        assert!(!chord.triggered(&press(&[LCTRL], T)));
+        assert!(!chord.triggered(&press(&[LCTRL, LALT, LSHIFT], T)));
+        assert!(!chord.triggered(&release(&[LCTRL, LALT], T)));
+
+        let hotkey = "Super+Space".parse::<Hotkey>().unwrap();
+        assert!(hotkey.steps()[0].triggered(&press(&[LSUPER], SPACE)));
+        assert!(hotkey.released(&release(&[SPACE], LSUPER)));
+        assert!(!hotkey.released(&release(&[LSUPER], SPACE)));
+    }
+
+    #[test]
+    fn it_matches_taps_and_releases() {
+        let chord = "Super".parse::<Chord>().unwrap();
+        assert!(!chord.triggered(&press(&[], LSUPER)));
+        assert!(chord.triggered(&release(&[], LSUPER)));
+        // not once another key was pressed along with it
+        assert!(!chord.triggered(&KeyEvent {
+            struck: state(&[LSUPER, SPACE]),
+            ..release(&[], LSUPER)
+        }));
+
+        // the tap is over once the last of the modifiers is let go of
+        let chord = "Ctrl+Shift".parse::<Chord>().unwrap();
+        assert!(!chord.triggered(&release(&[LCTRL], LSHIFT)));
+        assert!(chord.triggered(&KeyEvent {
+            struck: state(&[LCTRL, LSHIFT]),
+            ..release(&[], LCTRL)
+        }));
+        assert!(!chord.triggered(&release(&[], LCTRL)));
+
+        let chord = "Super+@Space".parse::<Chord>().unwrap();
+        assert!(!chord.triggered(&press(&[LSUPER], SPACE)));
+        assert!(chord.triggered(&release(&[LSUPER], SPACE)));
+        assert!(!chord.triggered(&release(&[], SPACE)));
+    }
+
+    #[test]
+    fn it_matches_keysyms() {
+        let mut keyboard = XkbKeyboard::new(&KeyboardSettings {
+            xkb: true,
+            layout: "us".into(),
+            variant: "dvorak".into(),
+            ..KeyboardSettings::default()
+        })
+        .unwrap();
+        let mut keys = [false; 256];
+        let mut event = |key: usize, pressed: bool| {
+            keys[key] = pressed;
+            KeyEvent {
+                key: key as u8,
+                pressed,
+                keys,
+                struck: keys,
+                xkb: Some(keyboard.update(key as u32, pressed)),
+            }
+        };
+
+        // t on dvorak is where k is on qwerty
+        let hotkey = "Ctrl+T".parse::<Hotkey>().unwrap();
+        let chord = &hotkey.steps()[0];
+        event(LCTRL, true);
+        assert!(!chord.triggered(&event(T, true)));
+        event(T, false);
+        assert!(chord.triggered(&event(K, true)));
+        event(K, false);
+        event(LSHIFT, true);
+        assert!(!chord.triggered(&event(K, true)));
+        event(K, false);
+        event(LSHIFT, false);
+        assert!(hotkey.released(&event(LCTRL, false)));
+
+        // xkb doesn't care which control key it was, but the chord does
+        let chord = "LCtrl+T".parse::<Chord>().unwrap();
+        assert!(!chord.triggered(&event(K, true)));
+        event(K, false);
+        event(RCTRL, true);
+        assert!(!chord.triggered(&event(K, true)));
+    }
+
+    #[test]
+    fn it_parses_actions() {
+        assert_eq!(
+            "toggle-launcher".parse::<Action>(),
+            Ok(Action::ToggleLauncher)
+        );
+        assert_eq!(
+            "mode-switcher".parse::<Action>(),
+            Ok(Action::Open(ListType::Switcher))
+        );
+        assert!("launch-rockets".parse::<Action>().is_err());
+    }
+}
@@ -1,47 +1,265 @@
-use crate::application::generate_application_list;
+use crate::config;
 use crate::utility::get_xdg_application_dirs;
 use cached::Cached;
-use inotify::{EventMask, Inotify, WatchMask};
-use std::path::Path;
+use directories::ProjectDirs;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use log::{info, warn};
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
 use std::{fs, thread};
-/// start inotify listeners - primarily used to invalidate parts of the cache
-pub fn listen() {
-    let mut inotify = Inotify::init().expect("Error while initializing inotify instance");
-    get_xdg_application_dirs().for_each(|x| {
-        inotify
-            .add_watch(x.clone(), WatchMask::CREATE)
-            .expect("Error while watching");
-        fs::read_dir(x)
-            .expect("error while reading xdg app dir files")
-            .filter_map(|y| y.ok())
-            .for_each(|y| {
-                inotify
-                    .add_watch(y.path(), WatchMask::MODIFY)
-                    .expect("Error while watching");
-            })
-    });
-    thread::spawn(move || loop {
-        generate_application_list();
+
+type ConfigListener = Box<dyn Fn(()) + Send + Sync + 'static>;
+
+/// called after config.toml changes and the settings have been reloaded
+static CONFIG_LISTENERS: Mutex<Vec<ConfigListener>> = Mutex::new(Vec::new());
+
+/// call a function whenever the config is reloaded
+pub fn on_config_change(listener: impl Fn(()) + Send + Sync + 'static) {
+    CONFIG_LISTENERS.lock().unwrap().push(Box::new(listener));
+}
+
+/// reset the cached settings and let everything which depends on them know
+fn reload_config() {
+    config::GET_SETTINGS.lock().unwrap().cache_reset();
+    CONFIG_LISTENERS.lock().unwrap().iter().for_each(|x| x(()));
+}
+
+/// reread the config and the desktop entries, for when the watches missed something
+pub fn reload() {
+    info!("Reloading the config and applications");
+    crate::application::PARSE_DESKTOP_ENTRY
+        .lock()
+        .unwrap()
+        .cache_reset();
+    crate::application::GENERATE_APPLICATION_LIST
+        .lock()
+        .unwrap()
+        .cache_reset();
+    reload_config();
+}
+
+fn listen_config() {
+    if let Some(x) = ProjectDirs::from(config::QUALIFIER, config::ORGANIZATION, config::APPLICATION)
+    {
+        watch_config(x.config_dir().to_path_buf(), reload_config);
+    }
+}
+
+/// watch config.toml and call `changed` whenever it is written, moved into place or removed
+/// the directory is watched rather than the file since editors tend to replace the file when saving
+fn watch_config(dir: PathBuf, changed: impl Fn() + Send + 'static) {
+    let mut inotify = match Inotify::init() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(
+                "Couldn't watch the config file, changes need a restart: {}",
+                e
+            );
+            return;
+        }
+    };
+    if let Err(e) = fs::create_dir_all(&dir).and_then(|_| {
+        inotify.add_watch(
+            &dir,
+            WatchMask::CLOSE_WRITE
+                | WatchMask::MOVED_TO
+                | WatchMask::MOVED_FROM
+                | WatchMask::DELETE,
+        )
+    }) {
+        warn!(
+            "Couldn't watch {}, config changes need a restart: {}",
+            dir.display(),
+            e
+        );
+        return;
+    }
+    thread::spawn(move || {
         let mut buffer = [0; 1024];
-        let mut events = inotify
-            .read_events_blocking(&mut buffer)
-            .expect("Error while reading events");
-        if events.any(|x| x.mask == EventMask::CREATE) {
-            crate::application::GENERATE_APPLICATION_LIST
-                .lock()
-                .unwrap()
-                .cache_reset();
+        loop {
+            let mut events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Stopped watching the config file: {}", e);
+                    return;
+                }
+            };
+            if !events.any(|x| x.name == Some(OsStr::new("config.toml"))) {
+                continue;
+            }
+            info!("Reloading {}", dir.join("config.toml").display());
+            changed();
+        }
+    });
+}
+
+/// watch a directory of desktop entries and every directory below it, as entries are looked for recursively
+/// anything which can't be watched is skipped, changes to it just aren't picked up without a `reload`
+fn add_application_watches(
+    inotify: &mut Inotify,
+    dir: &Path,
+    watches: &mut HashMap<WatchDescriptor, PathBuf>,
+) {
+    let mask = WatchMask::CREATE
+        | WatchMask::MODIFY
+        | WatchMask::DELETE
+        | WatchMask::MOVED_TO
+        | WatchMask::MOVED_FROM;
+    match inotify.add_watch(dir, mask) {
+        Ok(x) => {
+            watches.insert(x, dir.to_path_buf());
+        }
+        Err(e) => {
+            warn!(
+                "Couldn't watch {} for new applications: {}",
+                dir.display(),
+                e
+            );
+            return;
+        }
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(
+                "Couldn't read {} for new applications: {}",
+                dir.display(),
+                e
+            );
+            return;
+        }
+    };
+    entries
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_type().is_ok_and(|y| y.is_dir()))
+        .for_each(|x| add_application_watches(inotify, &x.path(), watches));
+}
+
+/// watch the application directories, calling `changed` with the path of each entry or directory which changes
+fn watch_applications(dirs: Vec<PathBuf>, changed: impl Fn(&Path) + Send + 'static) {
+    let mut inotify = match Inotify::init() {
+        Ok(x) => x,
+        Err(e) => {
+            warn!(
+                "Couldn't watch the application directories, new applications need a restart: {}",
+                e
+            );
+            return;
         }
-        if events.any(|x| x.mask == EventMask::MODIFY) {
-            events.filter(|x| x.name.is_some()).for_each(|x| {
-                let mut cache = crate::application::PARSE_DESKTOP_ENTRY.lock().unwrap();
-                get_xdg_application_dirs()
-                    .map(|y| y.join(Path::new(x.name.unwrap())))
-                    .for_each(|y| {
-                        // TODO confirm that this works by making sure its comparing equality and not identity
-                        cache.cache_remove(&y);
-                    });
-            })
+    };
+    let mut watches = HashMap::new();
+    dirs.iter()
+        .filter(|x| x.exists())
+        .for_each(|x| add_application_watches(&mut inotify, x, &mut watches));
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match inotify.read_events_blocking(&mut buffer) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!("Stopped watching the application directories: {}", e);
+                    return;
+                }
+            };
+            for event in events {
+                // the directory went away along with its watch
+                if event.mask.contains(EventMask::IGNORED) {
+                    watches.remove(&event.wd);
+                    continue;
+                }
+                let path = match (watches.get(&event.wd), event.name) {
+                    (Some(dir), Some(name)) => dir.join(name),
+                    _ => continue,
+                };
+                if event.mask.contains(EventMask::ISDIR)
+                    && event
+                        .mask
+                        .intersects(EventMask::CREATE | EventMask::MOVED_TO)
+                {
+                    add_application_watches(&mut inotify, &path, &mut watches);
+                }
+                changed(&path);
+            }
         }
     });
 }
+
+/// start inotify listeners - primarily used to invalidate parts of the cache
+pub fn listen() {
+    listen_config();
+    watch_applications(get_xdg_application_dirs().collect(), |path| {
+        crate::application::PARSE_DESKTOP_ENTRY
+            .lock()
+            .unwrap()
+            .cache_remove(&path.to_path_buf());
+        crate::application::GENERATE_APPLICATION_LIST
+            .lock()
+            .unwrap()
+            .cache_reset();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::inotify_listener::{watch_applications, watch_config};
+    use crate::test_dir::TempDir;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use std::{fs, iter};
+
+    #[test]
+    fn it_reloads_changed_config() {
+        let dir = TempDir::new("watch");
+        let (changed, changed_receiver) = mpsc::channel();
+        // the watch outlives the test, so the receiver is gone by the time the directory is removed
+        watch_config(dir.path().to_path_buf(), move || {
+            let _ = changed.send(());
+        });
+
+        fs::write(dir.join("other.toml"), "").unwrap();
+        fs::write(dir.join("config.toml"), "[window]\nwidth = 600\n").unwrap();
+        assert!(changed_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .is_ok());
+        // replacing the file the way editors do counts as well
+        fs::write(dir.join("config.toml.swp"), "").unwrap();
+        fs::rename(dir.join("config.toml.swp"), dir.join("config.toml")).unwrap();
+        assert!(changed_receiver
+            .recv_timeout(Duration::from_secs(5))
+            .is_ok());
+        assert!(changed_receiver
+            .recv_timeout(Duration::from_millis(200))
+            .is_err());
+    }
+
+    #[test]
+    fn it_watches_application_directories() {
+        let dir = TempDir::new("applications");
+        fs::create_dir(dir.join("kde")).unwrap();
+        fs::create_dir(dir.join("unreadable")).unwrap();
+        fs::set_permissions(dir.join("unreadable"), fs::Permissions::from_mode(0o000)).unwrap();
+        let (changed, changed_receiver) = mpsc::channel();
+        watch_applications(
+            vec![dir.path().to_path_buf(), dir.join("missing")],
+            move |x| {
+                let _ = changed.send(x.to_path_buf());
+            },
+        );
+        let changes_to = |path: &Path| {
+            iter::from_fn(|| changed_receiver.recv_timeout(Duration::from_secs(5)).ok())
+                .any(|x| x == path)
+        };
+
+        fs::write(dir.join("kde/foo.desktop"), "").unwrap();
+        assert!(changes_to(&dir.join("kde/foo.desktop")));
+        // directories made later are watched as well
+        fs::create_dir(dir.join("new")).unwrap();
+        assert!(changes_to(&dir.join("new")));
+        fs::write(dir.join("new/bar.desktop"), "").unwrap();
+        assert!(changes_to(&dir.join("new/bar.desktop")));
+    }
+}
@@ -1,78 +1,342 @@
+use crate::config::{get_settings, KeyboardSettings};
+use crate::error::LauncherError;
+use crate::hotkey;
+use crate::xkb::XkbKeyboard;
+use input::event::device::DeviceEvent;
 use input::event::keyboard::{KeyState, KeyboardEventTrait};
-use input::{Libinput, LibinputInterface};
+use input::event::EventTrait;
+use input::{DeviceCapability, Libinput, LibinputInterface};
 
+use log::{error, info};
 use std::fs::{File, OpenOptions};
-use std::os::unix::io::{FromRawFd, IntoRawFd, RawFd};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
 use std::path::Path;
-use std::{thread, time};
+use std::sync::mpsc::{self, SyncSender};
+use std::sync::Mutex;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+use std::{fs, io, mem, thread};
 
 extern crate libc;
 
-use input::event::Event::Keyboard;
+use input::event::Event::{Device, Keyboard};
 
-use libc::{O_RDONLY, O_RDWR, O_WRONLY};
-use std::borrow::Borrow;
+use libc::{O_ACCMODE, O_RDONLY, O_RDWR, O_WRONLY};
 use std::os::unix::fs::OpenOptionsExt;
 
+/// a key being pressed or released, along with every key which is held afterwards
+pub struct KeyEvent {
+    /// evdev code of the key
+    pub key: u8,
+    pub pressed: bool,
+    pub keys: [bool; 256],
+    /// every key pressed since none were held, to tell a tap of the modifiers apart from a combination
+    pub struck: [bool; 256],
+    /// what xkbcommon made of the event, if hotkeys are matched on keysyms
+    pub xkb: Option<XkbEvent>,
+}
+
+/// the keysym of a key in the configured layout and the modifiers active before and after it
+pub struct XkbEvent {
+    pub keysym: u32,
+    pub modifiers: Vec<&'static str>,
+    pub previous_modifiers: Vec<&'static str>,
+}
+
+type Predicate = Box<dyn Fn(&KeyEvent) -> bool + Send + Sync + 'static>;
+
 pub struct KeyboardShortcut {
-    predicate: Box<dyn Fn(u8, [bool; 256]) -> bool + Send + Sync + 'static>,
+    steps: Vec<Predicate>,
     executor: Box<dyn Fn(()) + Send + Sync + 'static>,
+    /// the next step to match, and until when it can be matched if it isn't the first
+    step: usize,
+    deadline: Instant,
 }
 impl KeyboardShortcut {
     pub fn new(
-        predicate: impl Fn(u8, [bool; 256]) -> bool + Send + Sync + Clone + 'static,
+        predicate: impl Fn(&KeyEvent) -> bool + Send + Sync + Clone + 'static,
+        executor: impl Fn(()) + Send + Sync + Clone + 'static,
+    ) -> Self {
+        KeyboardShortcut::sequence(vec![predicate], executor)
+    }
+
+    /// a shortcut which needs each of its steps matched in turn, each within the sequence timeout of the last
+    pub fn sequence(
+        steps: Vec<impl Fn(&KeyEvent) -> bool + Send + Sync + Clone + 'static>,
         executor: impl Fn(()) + Send + Sync + Clone + 'static,
     ) -> Self {
         KeyboardShortcut {
-            predicate: Box::new(predicate),
+            steps: steps
+                .into_iter()
+                .map(|x| Box::new(x) as Predicate)
+                .collect(),
             executor: Box::new(executor),
+            step: 0,
+            deadline: Instant::now(),
+        }
+    }
+
+    /// move along the steps, calling the executor once the last one matches
+    /// any other key pressed in between starts the sequence over, except for modifiers which may be part of the next step
+    fn handle(&mut self, event: &KeyEvent, now: Instant, timeout: Duration) {
+        if self.step > 0 && now > self.deadline {
+            self.step = 0;
+        }
+        if !(self.steps[self.step])(event) {
+            if self.step > 0 && event.pressed && !hotkey::is_modifier(event.key) {
+                self.step = 0;
+                // the key could be the start of the sequence again
+                self.handle(event, now, timeout);
+            }
+            return;
+        }
+        self.step += 1;
+        if self.step == self.steps.len() {
+            self.step = 0;
+            (self.executor)(());
+        } else {
+            self.deadline = now + timeout;
+        }
+    }
+}
+
+/// the shortcuts currently being listened for, swapped out when the hotkeys in the config change
+static SHORTCUTS: Mutex<Vec<KeyboardShortcut>> = Mutex::new(Vec::new());
+
+/// replace the shortcuts being listened for
+fn set_shortcuts(shortcuts: Vec<KeyboardShortcut>) {
+    *SHORTCUTS.lock().unwrap() = shortcuts;
+}
+
+/// a running listener thread and the eventfd used to tell it to stop
+struct Listener {
+    stop: RawFd,
+    thread: JoinHandle<()>,
+}
+
+static LISTENER: Mutex<Option<Listener>> = Mutex::new(None);
+
+/// listen for keyboard events
+/// predicate should determine based on the passed event if executor is called (or each step of a sequence does)
+/// and executor should do whatever you want on that event
+/// a listener which is already running is stopped first, so this also restarts it with fresh key state
+/// nothing is started if the input devices can't be read or libinput can't be given the seat
+pub fn listen(shortcuts: Vec<KeyboardShortcut>) -> Result<(), LauncherError> {
+    stop();
+    check_access()?;
+    set_shortcuts(shortcuts);
+
+    let stop = unsafe { libc::eventfd(0, libc::EFD_CLOEXEC) };
+    if stop == -1 {
+        error!(
+            "Couldn't create an eventfd for the keyboard listener: {}",
+            io::Error::last_os_error()
+        );
+        return Ok(());
+    }
+    // libinput has to stay on the thread, which says whether it got going
+    let (started, has_started) = mpsc::sync_channel(1);
+    let thread = thread::Builder::new()
+        .name("keyboard".into())
+        .spawn(move || run(stop, started));
+    let thread = match thread {
+        Ok(x) => x,
+        Err(e) => {
+            error!("Couldn't start the keyboard listener: {}", e);
+            unsafe { libc::close(stop) };
+            return Ok(());
+        }
+    };
+    let result = has_started.recv().unwrap_or_else(|_| {
+        Err(LauncherError::InputAccessError(
+            "the keyboard listener stopped while starting".into(),
+        ))
+    });
+    match result {
+        Ok(()) => *LISTENER.lock().unwrap() = Some(Listener { stop, thread }),
+        Err(_) => {
+            let _ = thread.join();
+            unsafe { libc::close(stop) };
+        }
+    }
+    result
+}
+
+/// make sure the input devices can be read at all, which usually takes being in the `input` group
+/// libinput only logs devices it can't open, so otherwise hotkeys would silently never fire
+pub fn check_access() -> Result<(), LauncherError> {
+    check_devices(Path::new("/dev/input"))
+}
+
+fn check_devices(dir: &Path) -> Result<(), LauncherError> {
+    let devices = fs::read_dir(dir)
+        .map_err(|e| LauncherError::InputAccessError(format!("{}: {}", dir.display(), e)))?
+        .filter_map(|x| x.ok())
+        .map(|x| x.path())
+        .filter(|x| {
+            x.file_name()
+                .and_then(|y| y.to_str())
+                .is_some_and(|y| y.starts_with("event"))
+        })
+        .collect::<Vec<_>>();
+    // one readable device will do, there is no telling which are keyboards before libinput opens them
+    let mut error = None;
+    for device in &devices {
+        match File::open(device) {
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                error.get_or_insert((device, e));
+            }
         }
     }
+    Err(LauncherError::InputAccessError(match error {
+        None => format!("no input devices in {}", dir.display()),
+        Some((x, e)) if e.kind() == io::ErrorKind::PermissionDenied => format!(
+            "permission denied opening {}, is this user in the input group?",
+            x.display()
+        ),
+        Some((x, e)) => format!("{}: {}", x.display(), e),
+    }))
 }
 
-pub fn listen(shortcuts: Vec<KeyboardShortcut>) {
-    thread::spawn(move || {
-        let mut state = [false; 256];
-        let mut input = Libinput::new_with_udev(Interface);
-        input.udev_assign_seat("seat0").unwrap();
-        loop {
-            input.dispatch().unwrap();
-            for event in &mut input {
-                if let Keyboard(key_event) = event {
+/// stop the listener thread, if there is one, and wait for it to close its devices
+pub fn stop() {
+    let listener = LISTENER.lock().unwrap().take();
+    if let Some(listener) = listener {
+        let one: u64 = 1;
+        unsafe {
+            libc::write(
+                listener.stop,
+                &one as *const u64 as *const libc::c_void,
+                mem::size_of::<u64>(),
+            );
+        }
+        if listener.thread.join().is_err() {
+            error!("The keyboard listener panicked");
+        }
+        unsafe { libc::close(listener.stop) };
+    }
+}
+
+/// dispatch libinput events as soon as its fd is readable, until the stop eventfd is
+fn run(stop: RawFd, started: SyncSender<Result<(), LauncherError>>) {
+    let mut state = [false; 256];
+    let mut struck = [false; 256];
+    let settings = get_settings().keyboard;
+    let timeout = Duration::from_millis(settings.sequence_timeout);
+    let mut xkb = keymap(&settings);
+    let mut input = Libinput::new_with_udev(Interface);
+    if input.udev_assign_seat("seat0").is_err() {
+        let _ = started.send(Err(LauncherError::InputAccessError(
+            "couldn't assign seat0 to libinput".into(),
+        )));
+        return;
+    }
+    let _ = started.send(Ok(()));
+    let mut fds = [
+        libc::pollfd {
+            fd: input.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: stop,
+            events: libc::POLLIN,
+            revents: 0,
+        },
+    ];
+    loop {
+        if let Err(e) = input.dispatch() {
+            error!("Couldn't read input events: {}", e);
+            return;
+        }
+        for event in &mut input {
+            match event {
+                Keyboard(key_event) => {
                     let key = key_event.key();
-                    state[key as usize] = match key_event.key_state() {
+                    if key as usize >= state.len() {
+                        continue;
+                    }
+                    let pressed = match key_event.key_state() {
                         KeyState::Pressed => true,
                         KeyState::Released => false,
                     };
-                    shortcuts.iter().for_each(|x| {
-                        let predicate: &(dyn Fn(u8, [bool; 256]) -> bool + Send + Sync + 'static) =
-                            x.predicate.borrow();
-                        if predicate(key as u8, state) {
-                            let exec: &(dyn Fn(()) + Send + Sync + 'static) = x.executor.borrow();
-                            exec(());
-                        }
-                    })
+                    state[key as usize] = pressed;
+                    struck[key as usize] |= pressed;
+                    let event = KeyEvent {
+                        key: key as u8,
+                        pressed,
+                        keys: state,
+                        struck,
+                        xkb: xkb.as_mut().map(|x| x.update(key, pressed)),
+                    };
+                    let now = Instant::now();
+                    SHORTCUTS
+                        .lock()
+                        .unwrap()
+                        .iter_mut()
+                        .for_each(|x| x.handle(&event, now, timeout));
+                    if !state.contains(&true) {
+                        struck = [false; 256];
+                    }
                 }
+                Device(DeviceEvent::Added(x))
+                    if x.device().has_capability(DeviceCapability::Keyboard) =>
+                {
+                    info!("Listening to keyboard {}", x.device().name());
+                }
+                // whatever was held on it never gets released, so start over rather than leave modifiers stuck
+                Device(DeviceEvent::Removed(x))
+                    if x.device().has_capability(DeviceCapability::Keyboard) =>
+                {
+                    info!("Keyboard {} went away", x.device().name());
+                    state = [false; 256];
+                    struck = [false; 256];
+                    xkb = keymap(&settings);
+                }
+                _ => {}
             }
+        }
 
-            // Libinput::dispatch is nonblocking so we need to wait a bit before checking again for the sake of our poor cpu
-            thread::sleep(time::Duration::from_millis(100));
+        if unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, -1) } == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::Interrupted {
+                continue;
+            }
+            error!("Couldn't wait for input events: {}", e);
+            return;
         }
-    });
+        if fds[1].revents != 0 {
+            return;
+        }
+    }
 }
 
-/// listen for keyboard events
-/// predicate should determine based on the passed state if executor is called
-/// and executor should do whatever you want on that event
+/// the xkbcommon state to match hotkeys with, if the config asks for one
+fn keymap(settings: &KeyboardSettings) -> Option<XkbKeyboard> {
+    if !settings.xkb {
+        return None;
+    }
+    let xkb = XkbKeyboard::new(settings);
+    if xkb.is_none() {
+        error!(
+            "Couldn't compile the keymap {:?}, matching hotkeys on physical keys instead",
+            settings
+        );
+    }
+    xkb
+}
 
 struct Interface;
 
 impl LibinputInterface for Interface {
     fn open_restricted(&mut self, path: &Path, flags: i32) -> Result<RawFd, i32> {
+        // O_RDONLY is 0 so the access mode has to be compared rather than masked
+        let access = flags & O_ACCMODE;
         OpenOptions::new()
             .custom_flags(flags)
-            .read((flags & O_RDONLY != 0) | (flags & O_RDWR != 0))
-            .write((flags & O_WRONLY != 0) | (flags & O_RDWR != 0))
+            .read(access == O_RDONLY || access == O_RDWR)
+            .write(access == O_WRONLY || access == O_RDWR)
             .open(path)
             .map(|file| file.into_raw_fd())
             .map_err(|err| err.raw_os_error().unwrap())
@@ -83,3 +347,81 @@ impl LibinputInterface for Interface {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::hotkey::Hotkey;
+    use crate::keyboard_listener::{check_devices, KeyEvent, KeyboardShortcut};
+    use crate::test_dir::TempDir;
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    const LCTRL: usize = 29;
+    const SPACE: usize = 57;
+    const Q: usize = 16;
+    const W: usize = 17;
+
+    #[test]
+    fn it_follows_sequences() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let counter = count.clone();
+        let steps = "Ctrl+Space; W"
+            .parse::<Hotkey>()
+            .unwrap()
+            .steps()
+            .into_iter()
+            .map(|x| move |y: &KeyEvent| x.triggered(y))
+            .collect::<Vec<_>>();
+        let mut shortcut = KeyboardShortcut::sequence(steps, move |()| {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let start = Instant::now();
+        let mut keys = [false; 256];
+        // press the keys in order and let go of them again, `ms` after the start
+        let mut type_keys = |chord: &[usize], ms: u64| {
+            let now = start + Duration::from_millis(ms);
+            let presses = chord.iter().map(|x| (*x, true));
+            let releases = chord.iter().rev().map(|x| (*x, false));
+            for (key, pressed) in presses.chain(releases) {
+                keys[key] = pressed;
+                let event = KeyEvent {
+                    key: key as u8,
+                    pressed,
+                    keys,
+                    struck: keys,
+                    xkb: None,
+                };
+                shortcut.handle(&event, now, Duration::from_secs(1));
+            }
+            count.load(Ordering::SeqCst)
+        };
+
+        assert_eq!(type_keys(&[LCTRL, SPACE], 0), 0);
+        assert_eq!(type_keys(&[W], 500), 1);
+        // another key in between starts over
+        type_keys(&[LCTRL, SPACE], 1000);
+        type_keys(&[Q], 1100);
+        assert_eq!(type_keys(&[W], 1200), 1);
+        // as does taking too long
+        type_keys(&[LCTRL, SPACE], 2000);
+        assert_eq!(type_keys(&[W], 3500), 1);
+        // though repeating the first step is just another start
+        type_keys(&[LCTRL, SPACE], 4000);
+        type_keys(&[LCTRL, SPACE], 4100);
+        assert_eq!(type_keys(&[W], 4200), 2);
+    }
+
+    #[test]
+    fn it_checks_device_access() {
+        let dir = TempDir::new("input");
+        assert!(check_devices(dir.path()).is_err());
+        // only the evdev nodes count
+        fs::write(dir.join("mice"), "").unwrap();
+        assert!(check_devices(dir.path()).is_err());
+        fs::write(dir.join("event0"), "").unwrap();
+        assert!(check_devices(dir.path()).is_ok());
+        assert!(check_devices(&dir.join("missing")).is_err());
+    }
+}
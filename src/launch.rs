@@ -0,0 +1,461 @@
+use crate::config;
+use crate::config::get_settings;
+use crate::error::LauncherError;
+use dbus::arg::{RefArg, Variant};
+use dbus::blocking::Connection;
+use log::{error, warn};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::unix::io::RawFd;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+extern crate libc;
+
+/// how long a launched program is watched for an immediate failure before the launch counts as a success
+const LAUNCH_GRACE_PERIOD: Duration = Duration::from_millis(200);
+
+/// how long to wait on the systemd user manager before giving up on moving a program into a scope
+const SYSTEMD_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// how long to wait on a DBusActivatable application, which may have to be started by the bus first
+const ACTIVATION_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// how launched programs are started, set by `launch.strategy` in the config
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LaunchStrategy {
+    /// as a detached child of the launcher
+    #[default]
+    Direct,
+    /// as a detached child which is then moved into its own transient systemd user scope
+    SystemdScope,
+}
+
+/// unit properties as passed to StartTransientUnit, `a(sv)`
+type UnitProperties<'a> = Vec<(&'a str, Variant<Box<dyn RefArg>>)>;
+
+/// escape a string for use in a systemd unit name the way `systemd-escape` does
+fn systemd_escape(value: &str) -> String {
+    value
+        .bytes()
+        .enumerate()
+        .map(|(i, x)| match x {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b':' | b'_' => (x as char).to_string(),
+            b'.' if i > 0 => ".".into(),
+            b'/' => "-".into(),
+            _ => format!("\\x{:02x}", x),
+        })
+        .collect()
+}
+
+/// the name of the scope for a launched program, `app-sky\x2dmenu-<id>-<pid>.scope` following the XDG cgroup naming convention
+fn scope_name(id: &str, pid: u32) -> String {
+    format!(
+        "app-{}-{}-{}.scope",
+        systemd_escape(config::APPLICATION),
+        systemd_escape(id.trim_end_matches(".desktop")),
+        pid
+    )
+}
+
+/// ask the systemd user manager to create a transient scope holding the given process
+fn start_scope(conn: &Connection, name: &str, pid: u32) -> Result<(), dbus::Error> {
+    let proxy = conn.with_proxy(
+        "org.freedesktop.systemd1",
+        "/org/freedesktop/systemd1",
+        SYSTEMD_TIMEOUT,
+    );
+    let properties: UnitProperties = vec![
+        ("PIDs", Variant(Box::new(vec![pid]))),
+        (
+            "CollectMode",
+            Variant(Box::new("inactive-or-failed".to_string())),
+        ),
+    ];
+    let auxiliary: Vec<(&str, UnitProperties)> = vec![];
+    let _: (dbus::Path,) = proxy.method_call(
+        "org.freedesktop.systemd1.Manager",
+        "StartTransientUnit",
+        (name, "fail", properties, auxiliary),
+    )?;
+    Ok(())
+}
+
+/// move a launched program out of our cgroup so it is accounted for separately and outlives the launcher
+/// without a user manager it simply stays where it is, as with the direct strategy
+fn move_into_scope(id: &str, pid: u32) {
+    let name = scope_name(id, pid);
+    if let Err(e) = Connection::new_session().and_then(|x| start_scope(&x, &name, pid)) {
+        warn!("Couldn't start systemd scope {}: {}", name, e);
+    }
+}
+
+/// the well-known name and object path a DBusActivatable application owns, both derived from its desktop file id
+/// e.g. `org.gnome.Weather.desktop` is `org.gnome.Weather` at `/org/gnome/Weather`
+fn activation_names(id: &str) -> (String, String) {
+    let name = id.trim_end_matches(".desktop").to_string();
+    let path = format!("/{}", name.replace('.', "/").replace('-', "_"));
+    (name, path)
+}
+
+/// call `org.freedesktop.Application.Activate`, or `ActivateAction` for a desktop action, on an application
+/// the bus starts it from its service file if it isn't running yet
+fn activate(
+    conn: &Connection,
+    id: &str,
+    action: &str,
+    startup_id: &str,
+) -> Result<(), dbus::Error> {
+    let (name, path) = activation_names(id);
+    let proxy = conn.with_proxy(name, path, ACTIVATION_TIMEOUT);
+    let mut platform_data: HashMap<&str, Variant<Box<dyn RefArg>>> = HashMap::new();
+    if !startup_id.is_empty() {
+        for key in &["desktop-startup-id", "activation-token"] {
+            platform_data.insert(key, Variant(Box::new(startup_id.to_string())));
+        }
+    }
+    if action.is_empty() {
+        proxy.method_call("org.freedesktop.Application", "Activate", (platform_data,))
+    } else {
+        let parameters: Vec<Variant<Box<dyn RefArg>>> = vec![];
+        proxy.method_call(
+            "org.freedesktop.Application",
+            "ActivateAction",
+            (action, parameters, platform_data),
+        )
+    }
+}
+
+/// launch a DBusActivatable application (or one of its actions) over the session bus
+pub fn dbus_activate(id: &str, action: &str, startup_id: &str) -> Result<(), LauncherError> {
+    Connection::new_session()
+        .and_then(|x| activate(&x, id, action, startup_id))
+        .map_err(|e| LauncherError::LaunchError(format!("{}: {}", id, e)))
+}
+
+/// file descriptors above stdio which are open in this process
+fn open_fds() -> Vec<RawFd> {
+    fs::read_dir("/proc/self/fd")
+        .map(|x| {
+            x.filter_map(|y| y.ok())
+                .filter_map(|y| y.file_name().to_str()?.parse::<RawFd>().ok())
+                .filter(|y| *y > 2)
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// spawn a command fully detached from the launcher - in its own session, with stdio on /dev/null
+/// and without inheriting any of our file descriptors (qt, libinput and friends don't all set CLOEXEC)
+pub fn spawn_detached(command: &mut Command) -> io::Result<Child> {
+    let fds = open_fds();
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    unsafe {
+        command.pre_exec(move || {
+            if libc::setsid() == -1 {
+                return Err(io::Error::last_os_error());
+            }
+            // closing them outright would also close the pipe std uses to report a failed exec
+            for fd in &fds {
+                libc::fcntl(*fd, libc::F_SETFD, libc::FD_CLOEXEC);
+            }
+            Ok(())
+        });
+    }
+    command.spawn()
+}
+
+/// wait on a launched program in the background so it doesn't linger as a zombie once it exits
+pub fn reap(mut child: Child) {
+    if let Err(e) = thread::Builder::new()
+        .name("reaper".into())
+        .stack_size(64 * 1024)
+        .spawn(move || child.wait())
+    {
+        warn!("Couldn't start a thread to reap a launched program: {}", e);
+    }
+}
+
+/// launch a program directly from its argument vector - desktop entries never go through a shell
+/// a program which fails straight away (e.g. on bad arguments) is reported as a failed launch
+pub fn exec_args(
+    id: &str,
+    args: &[String],
+    working_dir: &str,
+    startup_id: &str,
+) -> Result<(), LauncherError> {
+    let mut command = Command::new(&args[0]);
+    command.args(&args[1..]);
+    if !working_dir.is_empty() {
+        command.current_dir(working_dir);
+    }
+    // never pass on the id we were started with ourselves
+    for key in &["DESKTOP_STARTUP_ID", "XDG_ACTIVATION_TOKEN"] {
+        if startup_id.is_empty() {
+            command.env_remove(key);
+        } else {
+            command.env(key, startup_id);
+        }
+    }
+    let mut child = spawn_detached(&mut command)
+        .map_err(|e| LauncherError::LaunchError(format!("{}: {}", args[0], e)))?;
+    if get_settings().launch.strategy == LaunchStrategy::SystemdScope {
+        move_into_scope(id, child.id());
+    }
+
+    let start = Instant::now();
+    while start.elapsed() < LAUNCH_GRACE_PERIOD {
+        match child.try_wait() {
+            Ok(Some(status)) if !status.success() => {
+                return Err(LauncherError::LaunchError(format!(
+                    "{} exited immediately ({})",
+                    args[0], status
+                )))
+            }
+            Ok(Some(_)) => return Ok(()),
+            Ok(None) => thread::sleep(Duration::from_millis(20)),
+            Err(e) => return Err(LauncherError::LaunchError(format!("{}: {}", args[0], e))),
+        }
+    }
+    reap(child);
+    Ok(())
+}
+
+/// run a shell command, used for our own commands such as focusing windows in the switcher
+pub fn exec_string(cmd: String) -> bool {
+    match spawn_detached(Command::new("sh").arg("-c").arg(cmd)) {
+        Ok(child) => {
+            reap(child);
+            true
+        }
+        Err(e) => {
+            error!("Couldn't launch program: {}", e);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::launch::{
+        activate, activation_names, exec_args, scope_name, spawn_detached, start_scope,
+        systemd_escape,
+    };
+    use crate::test_bus::PrivateBus;
+    use dbus::arg::{RefArg, Variant};
+    use dbus::channel::MatchingReceiver;
+    use dbus::message::MatchRule;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+    use std::process::Command;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn args(x: &[&str]) -> Vec<String> {
+        x.iter().map(|y| y.to_string()).collect()
+    }
+
+    #[test]
+    fn it_reports_failed_launches() {
+        assert!(exec_args("test.desktop", &args(&["sleep", "1"]), "", "").is_ok());
+        assert!(exec_args("test.desktop", &args(&["true"]), "", "").is_ok());
+        assert!(exec_args("test.desktop", &args(&["sh", "-c", "exit 3"]), "", "").is_err());
+        assert!(exec_args("test.desktop", &args(&["sky-menu-missing-binary"]), "", "").is_err());
+        assert!(exec_args(
+            "test.desktop",
+            &args(&["true"]),
+            "/sky-menu-missing-directory",
+            ""
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn it_detaches_launched_programs() {
+        let mut child = spawn_detached(Command::new("sleep").arg("10")).unwrap();
+        let pid = child.id() as libc::pid_t;
+        // it leads its own session and has no terminal or inherited stdio
+        let sid = unsafe { libc::getsid(pid) };
+        let stdin = fs::read_link(format!("/proc/{}/fd/0", pid));
+        child.kill().unwrap();
+        child.wait().unwrap();
+        assert_eq!(sid, pid);
+        assert_eq!(stdin.unwrap(), Path::new("/dev/null"));
+    }
+
+    #[test]
+    fn it_names_scopes() {
+        assert_eq!(systemd_escape("sky-menu"), "sky\\x2dmenu");
+        assert_eq!(systemd_escape("org.gnome.Nautilus"), "org.gnome.Nautilus");
+        assert_eq!(systemd_escape(".hidden app"), "\\x2ehidden\\x20app");
+        assert_eq!(
+            scope_name("kde-org.kde.konsole.desktop", 42),
+            "app-sky\\x2dmenu-kde\\x2dorg.kde.konsole-42.scope"
+        );
+    }
+
+    #[test]
+    fn it_starts_systemd_scopes() {
+        let bus = PrivateBus::new();
+        let (ready, ready_receiver) = mpsc::channel();
+        let (calls, call_receiver) = mpsc::channel();
+
+        // a stand-in for the systemd user manager which records StartTransientUnit calls
+        let address = bus.address.clone();
+        thread::spawn(move || {
+            let systemd = PrivateBus::connect_to(&address);
+            systemd
+                .request_name("org.freedesktop.systemd1", false, true, true)
+                .unwrap();
+            systemd.start_receive(
+                MatchRule::new_method_call(),
+                Box::new(move |msg, conn| {
+                    let (name, mode, properties) = msg
+                        .read3::<String, String, Vec<(String, Variant<Box<dyn RefArg>>)>>()
+                        .unwrap();
+                    let pids = properties
+                        .iter()
+                        .filter(|x| x.0 == "PIDs")
+                        .flat_map(|x| x.1 .0.as_iter().unwrap())
+                        .filter_map(|x| x.as_u64())
+                        .collect::<Vec<u64>>();
+                    calls
+                        .send((msg.member().unwrap().to_string(), name, mode, pids))
+                        .unwrap();
+                    let reply = msg
+                        .method_return()
+                        .append1(dbus::Path::from("/org/freedesktop/systemd1/job/1"));
+                    conn.channel().send(reply).unwrap();
+                    true
+                }),
+            );
+            ready.send(()).unwrap();
+            while systemd.process(Duration::from_secs(5)).unwrap_or(false) {}
+        });
+        ready_receiver.recv().unwrap();
+
+        start_scope(&bus.connect(), "app-test-42.scope", 42).unwrap();
+        assert_eq!(
+            call_receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            (
+                "StartTransientUnit".to_string(),
+                "app-test-42.scope".to_string(),
+                "fail".to_string(),
+                vec![42]
+            )
+        );
+    }
+
+    #[test]
+    fn it_derives_activation_names() {
+        assert_eq!(
+            activation_names("org.gnome.Weather.desktop"),
+            ("org.gnome.Weather".into(), "/org/gnome/Weather".into())
+        );
+        assert_eq!(
+            activation_names("org.example.my-app.desktop"),
+            ("org.example.my-app".into(), "/org/example/my_app".into())
+        );
+    }
+
+    #[test]
+    fn it_activates_applications() {
+        let bus = PrivateBus::new();
+        let (ready, ready_receiver) = mpsc::channel();
+        let (calls, call_receiver) = mpsc::channel();
+
+        // a stand-in application which records the org.freedesktop.Application calls it gets
+        let address = bus.address.clone();
+        thread::spawn(move || {
+            let app = PrivateBus::connect_to(&address);
+            app.request_name("org.example.App", false, true, true)
+                .unwrap();
+            app.start_receive(
+                MatchRule::new_method_call(),
+                Box::new(move |msg, conn| {
+                    // platform data is the last argument of both methods
+                    let count = msg.iter_init().count();
+                    let mut arguments = msg.iter_init();
+                    for _ in 1..count {
+                        arguments.next();
+                    }
+                    let platform_data: HashMap<String, Variant<Box<dyn RefArg>>> =
+                        arguments.read().unwrap();
+                    let startup_id = platform_data
+                        .get("desktop-startup-id")
+                        .and_then(|x| x.0.as_str())
+                        .unwrap_or("")
+                        .to_string();
+                    calls
+                        .send((
+                            msg.path().unwrap().to_string(),
+                            msg.member().unwrap().to_string(),
+                            msg.get1::<String>().unwrap_or_default(),
+                            startup_id,
+                        ))
+                        .unwrap();
+                    conn.channel().send(msg.method_return()).unwrap();
+                    true
+                }),
+            );
+            ready.send(()).unwrap();
+            while app.process(Duration::from_secs(5)).unwrap_or(false) {}
+        });
+        ready_receiver.recv().unwrap();
+
+        let conn = bus.connect();
+        activate(&conn, "org.example.App.desktop", "", "").unwrap();
+        activate(
+            &conn,
+            "org.example.App.desktop",
+            "new-window",
+            "sky-menu-1-host-app-0",
+        )
+        .unwrap();
+        assert!(activate(&conn, "org.example.Missing.desktop", "", "").is_err());
+        let received = call_receiver
+            .iter()
+            .take(2)
+            .collect::<Vec<(String, String, String, String)>>();
+        assert_eq!(
+            received,
+            vec![
+                (
+                    "/org/example/App".to_string(),
+                    "Activate".to_string(),
+                    "".to_string(),
+                    "".to_string()
+                ),
+                (
+                    "/org/example/App".to_string(),
+                    "ActivateAction".to_string(),
+                    "new-window".to_string(),
+                    "sky-menu-1-host-app-0".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn it_passes_startup_ids() {
+        let check = "[ \"$DESKTOP_STARTUP_ID\" = sky-menu-1-host-sh-0 ] && [ \"$XDG_ACTIVATION_TOKEN\" = sky-menu-1-host-sh-0 ]";
+        assert!(exec_args(
+            "test.desktop",
+            &args(&["sh", "-c", check]),
+            "",
+            "sky-menu-1-host-sh-0"
+        )
+        .is_ok());
+        assert!(exec_args("test.desktop", &args(&["sh", "-c", check]), "", "").is_err());
+    }
+}
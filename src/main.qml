@@ -11,11 +11,18 @@ Window {
     flags: Qt.Dialog | Qt.FramelessWindowHint
     color: "#00000000"
     title: "Launcher"
+    // the query input and each result get one row
+    property real row_height: config.height / (config.results + 1)
 
     SystemPalette { id: palette; colorGroup: SystemPalette.Active }
 
     SearchableList {
         id: launcher
+        onLaunch_failed: {
+            error_text.text = message
+            error_banner.visible = true
+        }
+        onQuery_requested: query_input.text = query
     }
 
     Shortcut {
@@ -37,6 +44,10 @@ Window {
 
     Config {
         id: config
+        onConfig_changed: {
+            setX(config.positioned ? config.x : Screen.width / 2 - config.width / 2)
+            setY(config.positioned ? config.y : Screen.height / 2 - config.height / 2)
+        }
     }
 
     onVisibleChanged: {
@@ -44,6 +55,9 @@ Window {
             raise()
             query_input.text = ""
         }
+        // unlike a failed launch, a notice comes back every time until whatever is wrong gets fixed
+        error_text.text = launcher.notice
+        error_banner.visible = visible && launcher.notice !== ""
     }
 
     onActiveChanged: {
@@ -55,8 +69,6 @@ Window {
     Component.onCompleted: {
         launcher.setup()
         config.setup()
-        setX(Screen.width / 2 - width / 2)
-        setY(Screen.height / 2 - height / 2)
     }
 
     Item {
@@ -66,12 +78,12 @@ Window {
             color: palette.window
             border.width: 0
             opacity: 0.5
-            height: config.height * (launcher.model_len + 1) * .1
+            height: row_height * (launcher.model_len + 1)
             width: config.width
         }
 
         Rectangle {
-            height: config.height * .1
+            height: row_height
             color: palette.base
             anchors.left: parent.left
             anchors.right: parent.right
@@ -81,14 +93,17 @@ Window {
                 color:  palette.text
                 anchors.leftMargin: 30
                 horizontalAlignment: Text.AlignLeft
-                font.pointSize: 16
+                font.pointSize: config.font_size
                 renderType: Text.QtRendering
                 cursorVisible: true
-                font.family: "Iosevka Aile"
+                font.family: config.font_family
                 verticalAlignment: Text.AlignVCenter
                 anchors.fill: parent
                 focus: true
-                onTextChanged: launcher.search(text)
+                onTextChanged: {
+                    error_banner.visible = false
+                    launcher.search(text)
+                }
                 Keys.onUpPressed: launcher.up()
                 Keys.onDownPressed: launcher.down()
                 Keys.onReturnPressed: launcher.launch()
@@ -100,7 +115,7 @@ Window {
             anchors.bottom: parent.bottom
             anchors.right: parent.right
             anchors.left: parent.left
-            height: config.height * .9
+            height: row_height * config.results
             currentIndex: launcher.selected
             highlightMoveDuration: 0
             model: launcher.model
@@ -109,23 +124,45 @@ Window {
             }
             delegate: Item {
                 id: listItem
-                height: config.height * 0.1
+                height: row_height
                 width: config.width
                 Image {
-                    height: config.height * 0.1
-                    width: config.height * 0.1
+                    height: row_height
+                    width: row_height
                     source: launcher.icon(icon)
                 }
                 Text {
-                    leftPadding: config.height * 0.1
+                    leftPadding: row_height
                     text: name
                     anchors.fill: parent
-                    font.family: "Iosevka Aile"
+                    font.family: config.font_family
                     verticalAlignment: Text.AlignVCenter
-                    font.pointSize: 16
+                    font.pointSize: config.font_size
                     color: listItem.ListView.isCurrentItem ? palette.highlightedText : palette.windowText
                 }
             }
         }
+
+        Rectangle {
+            id: error_banner
+            visible: false
+            y: row_height
+            height: row_height
+            anchors.left: parent.left
+            anchors.right: parent.right
+            color: "#c0392b"
+
+            Text {
+                id: error_text
+                leftPadding: 30
+                rightPadding: 30
+                anchors.fill: parent
+                elide: Text.ElideRight
+                font.family: config.font_family
+                verticalAlignment: Text.AlignVCenter
+                font.pointSize: Math.max(1, Math.round(config.font_size * .75))
+                color: "white"
+            }
+        }
     }
 }
@@ -1,21 +1,50 @@
 mod application;
+mod cli;
 mod config;
+mod control_socket;
+mod dbus_service;
 mod error;
+mod hotkey;
 mod icon;
 mod inotify_listener;
 mod keyboard_listener;
+mod launch;
 mod searchable_list;
+mod startup;
+mod terminal;
+#[cfg(test)]
+mod test_bus;
+#[cfg(test)]
+mod test_dir;
 mod utility;
+mod xkb;
 
 use crate::searchable_list::*;
 use cstr::*;
 
+use crate::cli::Subcommand;
 use crate::config::Config;
 use log::log;
 use qmetaobject::*;
+use std::process;
 
 fn main() {
     env_logger::init();
+    let args = std::env::args().skip(1).collect::<Vec<String>>();
+    match cli::parse(&args) {
+        Ok(Subcommand::Daemon { replace }) => {
+            let _lock = cli::single_instance(replace).unwrap_or_else(|code| process::exit(code));
+            daemon()
+        }
+        Ok(x) => process::exit(cli::run(x)),
+        Err(e) => {
+            eprintln!("{}\n\n{}", e, cli::USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn daemon() {
     install_message_handler(logger);
 
     inotify_listener::listen();
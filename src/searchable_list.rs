@@ -1,31 +1,41 @@
-use crate::application::generate_application_list;
+use crate::application::{find_application, generate_application_list};
 use crate::config;
 use crate::config::UsageCount;
+use crate::config::{get_settings, Weights};
+use crate::control_socket;
+use crate::control_socket::Command;
+use crate::dbus_service;
+use crate::error::LauncherError;
+use crate::hotkey::Action;
 use crate::icon::lookup_icon;
+use crate::inotify_listener;
 use crate::keyboard_listener;
-use crate::keyboard_listener::KeyboardShortcut;
+use crate::keyboard_listener::{KeyEvent, KeyboardShortcut};
+use crate::launch::{dbus_activate, exec_args, exec_string};
+use crate::startup::Startup;
+use crate::terminal::{terminal_command, wrap};
 use crate::utility::get_running_applications;
 use directories::ProjectDirs;
 use fuzzy_matcher::skim::SkimMatcherV2;
 use fuzzy_matcher::FuzzyMatcher;
 use itertools::Itertools;
-use log::{error};
+use log::{error, info, warn};
 use qmetaobject::*;
+use serde::Deserialize;
 use std::cell::RefCell;
-use std::process::Command;
-
-#[derive(PartialEq, Eq)]
-enum ListType {
+use std::process;
+use std::sync::Mutex;
+use std::thread;
+
+/// the modes the list can be opened in, named in the config as `launcher` and `switcher`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ListType {
+    #[default]
     Launcher,
     Switcher,
 }
 
-impl Default for ListType {
-    fn default() -> Self {
-        ListType::Launcher
-    }
-}
-
 #[derive(QObject, Default)]
 pub struct SearchableList {
     base: qt_base_class!(trait QObject),
@@ -36,11 +46,19 @@ pub struct SearchableList {
     focus: qt_property!(bool; NOTIFY focus_changed),
     model_len: qt_property!(i32; NOTIFY model_len_changed),
     hide_on_lost_focus: qt_property!(bool;),
+    /// a problem the user should know about whenever the list is opened, like hotkeys not working
+    notice: qt_property!(QString; NOTIFY notice_changed),
 
     usage_count: UsageCount,
 
     list_type: ListType,
 
+    /// the launcher on the session bus, if it could be offered there
+    bus: Option<dbus_service::Service>,
+
+    /// set while an application is starting, so pressing enter again doesn't start a second one
+    launching: bool,
+
     setup: qt_method!(fn(&mut self)),
     up: qt_method!(fn(&mut self)),
     down: qt_method!(fn(&mut self)),
@@ -55,53 +73,123 @@ pub struct SearchableList {
     selected_changed: qt_signal!(),
     focus_changed: qt_signal!(),
     model_len_changed: qt_signal!(),
+    launch_failed: qt_signal!(message: QString),
+    notice_changed: qt_signal!(),
+    query_requested: qt_signal!(query: QString),
 }
 
 impl SearchableList {
     fn setup(&mut self) {
-        self.visible = false;
-        self.visible_changed();
+        self.set_visible(false);
 
         self.focus = true;
         self.focus_changed();
 
-        let launcher_qpointer = QPointer::from(&*self);
-        let toggle_launcher = qmetaobject::queued_callback(move |()| {
-            if let Some(qself) = launcher_qpointer.as_pinned() {
-                qself.borrow_mut().list_type = ListType::Launcher;
-                qself.borrow_mut().hide_on_lost_focus = true;
-                qself.borrow_mut().visible = !qself.borrow().visible;
-                qself.borrow().visible_changed();
-                qself.borrow_mut().focus = true;
-                qself.borrow().focus_changed();
-                qself.borrow_mut().search("".into())
+        let toggle_qpointer = QPointer::from(&*self);
+        let toggle = qmetaobject::queued_callback(move |mode: ListType| {
+            if !get_settings().modes.contains(&mode) {
+                return;
+            }
+            if let Some(qself) = toggle_qpointer.as_pinned() {
+                if qself.borrow().visible {
+                    qself.borrow_mut().hide();
+                } else {
+                    qself.borrow_mut().open(mode, true);
+                }
             }
         });
 
-        let switcher_qpointer = QPointer::from(&*self);
-        let show_switcher = qmetaobject::queued_callback(move |()| {
-            if let Some(qself) = switcher_qpointer.as_pinned() {
+        // shown until told otherwise, for as long as a hotkey is held or until a `hide` over the control socket
+        let show_qpointer = QPointer::from(&*self);
+        let show = qmetaobject::queued_callback(move |mode: ListType| {
+            if !get_settings().modes.contains(&mode) {
+                return;
+            }
+            if let Some(qself) = show_qpointer.as_pinned() {
                 if qself.borrow().visible {
                     return;
                 }
-                qself.borrow_mut().hide_on_lost_focus = false;
-                qself.borrow_mut().list_type = ListType::Switcher;
-                qself.borrow_mut().visible = true;
-                qself.borrow().visible_changed();
-                qself.borrow_mut().focus = true;
-                qself.borrow().focus_changed();
-                qself.borrow_mut().search("".into())
+                qself.borrow_mut().open(mode, false);
             }
         });
 
         let hide_switcher_qpointer = QPointer::from(&*self);
         let hide_switcher = qmetaobject::queued_callback(move |()| {
             if let Some(qself) = hide_switcher_qpointer.as_pinned() {
-                qself.borrow_mut().list_type = ListType::Switcher;
-                qself.borrow_mut().visible = false;
-                qself.borrow().visible_changed();
+                // leave the list alone if it was opened some other way
+                if qself.borrow().list_type != ListType::Switcher
+                    || qself.borrow().hide_on_lost_focus
+                {
+                    return;
+                }
+                qself.borrow_mut().set_visible(false);
+            }
+        });
+
+        let mode_qpointer = QPointer::from(&*self);
+        let open_mode = qmetaobject::queued_callback(move |mode: ListType| {
+            if !get_settings().modes.contains(&mode) {
+                return;
+            }
+            if let Some(qself) = mode_qpointer.as_pinned() {
+                qself.borrow_mut().open(mode, true);
+            }
+        });
+
+        let hide_qpointer = QPointer::from(&*self);
+        let hide = qmetaobject::queued_callback(move |()| {
+            if let Some(qself) = hide_qpointer.as_pinned() {
+                qself.borrow_mut().hide();
+            }
+        });
+
+        // the query goes through the input box in qml, so it shows up there as well
+        let search_qpointer = QPointer::from(&*self);
+        let search = qmetaobject::queued_callback(move |query: String| {
+            if let Some(qself) = search_qpointer.as_pinned() {
+                if !qself.borrow().visible {
+                    let mode = qself.borrow().list_type;
+                    qself.borrow_mut().open(mode, true);
+                }
+                qself.borrow().query_requested(QString::from(query));
             }
         });
+
+        let commands = {
+            let (toggle, show, open_mode) = (toggle.clone(), show.clone(), open_mode.clone());
+            move |command: Command| match command {
+                Command::Toggle(mode) => toggle(mode),
+                Command::Show(mode) => show(mode),
+                Command::Hide => hide(()),
+                Command::Search(query) => search(query),
+                Command::Mode(mode) => open_mode(mode),
+                Command::Reload => inotify_listener::reload(),
+                // straight away rather than through qt, usage counts are saved as they change anyway
+                Command::Quit => {
+                    info!("Quitting so another instance can take over");
+                    process::exit(0)
+                }
+            }
+        };
+        control_socket::listen(commands.clone());
+
+        // launching happens off the qt thread so a failure can be the reply, only the usage count needs qt
+        let launched_qpointer = QPointer::from(&*self);
+        let launched = qmetaobject::queued_callback(move |app: Application| {
+            if let Some(qself) = launched_qpointer.as_pinned() {
+                qself
+                    .borrow_mut()
+                    .usage_count
+                    .inc_or(&app.usage_key(), &app.name);
+            }
+        });
+        self.bus = dbus_service::listen(commands, move |id| {
+            let app = find_application(id)?;
+            app.try_exec()?;
+            launched(app);
+            Ok(())
+        });
+
         if let Some(proj_dirs) =
             ProjectDirs::from(config::QUALIFIER, config::ORGANIZATION, config::APPLICATION)
         {
@@ -110,23 +198,90 @@ impl SearchableList {
 
         self.search("".into());
 
-        const SPACE_KEY_CODE: usize = 57;
-        const TAB_KEY_CODE: usize = 15;
-        const LCTRL_KEY_CODE: usize = 29;
-        const LALT_KEY_CODE: usize = 56;
+        let shortcuts = move || {
+            get_settings()
+                .bindings()
+                .into_iter()
+                .flat_map(|(action, hotkey)| {
+                    let steps = hotkey
+                        .steps()
+                        .into_iter()
+                        .map(|x| move |y: &KeyEvent| x.triggered(y))
+                        .collect::<Vec<_>>();
+                    match action {
+                        Action::ToggleLauncher => {
+                            let toggle = toggle.clone();
+                            vec![KeyboardShortcut::sequence(steps, move |()| {
+                                toggle(ListType::Launcher)
+                            })]
+                        }
+                        // the switcher goes away again once the modifiers are let go of
+                        Action::ShowSwitcher if hotkey.held() => vec![
+                            KeyboardShortcut::sequence(steps, {
+                                let show = show.clone();
+                                move |()| show(ListType::Switcher)
+                            }),
+                            KeyboardShortcut::new(
+                                move |x: &KeyEvent| hotkey.released(x),
+                                hide_switcher.clone(),
+                            ),
+                        ],
+                        // unless there is nothing left held, in which case it stays open like any other mode
+                        Action::ShowSwitcher => {
+                            let open_mode = open_mode.clone();
+                            vec![KeyboardShortcut::sequence(steps, move |()| {
+                                open_mode(ListType::Switcher)
+                            })]
+                        }
+                        Action::Open(mode) => {
+                            let open_mode = open_mode.clone();
+                            vec![KeyboardShortcut::sequence(steps, move |()| open_mode(mode))]
+                        }
+                    }
+                })
+                .collect::<Vec<KeyboardShortcut>>()
+        };
+        // hotkeys can be left to the window manager instead, bound to commands on the control socket
+        let listen = move || {
+            if !get_settings().keyboard.libinput {
+                keyboard_listener::stop();
+                return Ok(());
+            }
+            keyboard_listener::listen(shortcuts())
+        };
+        // cleared again once whatever was wrong is fixed
+        let notice = |result: Result<(), LauncherError>| match result {
+            Ok(()) => String::new(),
+            Err(e) => {
+                error!("{}", e);
+                e.to_string()
+            }
+        };
+        self.notice = QString::from(notice(listen()));
+        self.notice_changed();
+
+        let notice_qpointer = QPointer::from(&*self);
+        let set_notice = qmetaobject::queued_callback(move |notice: String| {
+            if let Some(qself) = notice_qpointer.as_pinned() {
+                qself.borrow_mut().notice = QString::from(notice);
+                qself.borrow().notice_changed();
+            }
+        });
 
-        let shortcuts = vec![
-            KeyboardShortcut::new(
-                |_key, x| x[LCTRL_KEY_CODE] && x[SPACE_KEY_CODE],
-                toggle_launcher.clone(),
-            ),
-            KeyboardShortcut::new(|_key, x| x[LALT_KEY_CODE] && x[TAB_KEY_CODE], show_switcher),
-            KeyboardShortcut::new(
-                |key, x| key as usize == LALT_KEY_CODE && !x[LALT_KEY_CODE],
-                hide_switcher,
-            ),
-        ];
-        keyboard_listener::listen(shortcuts);
+        // only restart the listener when the hotkeys or keyboard settings actually changed, not on every edit to the config
+        let keyboard = || {
+            let settings = get_settings();
+            (settings.hotkeys, settings.keyboard)
+        };
+        let registered = Mutex::new(keyboard());
+        inotify_listener::on_config_change(move |()| {
+            let current = keyboard();
+            let mut registered = registered.lock().unwrap();
+            if *registered != current {
+                set_notice(notice(listen()));
+                *registered = current;
+            }
+        });
     }
 
     fn set_selected(&mut self, index: i32) {
@@ -153,37 +308,89 @@ impl SearchableList {
     }
 
     fn launch(&mut self) {
-        if self.model.borrow().row_count() == 0 {
+        if self.launching || self.model.borrow().row_count() == 0 {
+            return;
+        }
+
+        // selecting a window already focused it
+        if self.list_type == ListType::Switcher {
+            self.hide();
             return;
         }
 
         let app = self.model.borrow()[self.selected as usize].clone();
+        self.launching = true;
+
+        // seeing whether it started (or waiting on dbus activation) would freeze the window on the qt thread
+        let qpointer = QPointer::from(&*self);
+        let launching = app.clone();
+        let finished = qmetaobject::queued_callback(move |result: Result<(), LauncherError>| {
+            if let Some(qself) = qpointer.as_pinned() {
+                qself.borrow_mut().finish_launch(&app, result);
+            }
+        });
+        thread::spawn(move || finished(launching.try_exec()));
+    }
 
-        if app.try_exec() {
-            self.usage_count.inc(&app.name)
-        };
-        self.hide();
+    fn finish_launch(&mut self, app: &Application, result: Result<(), LauncherError>) {
+        self.launching = false;
+        match result {
+            Ok(()) => {
+                self.usage_count.inc_or(&app.usage_key(), &app.name);
+                if let Some(x) = &self.bus {
+                    x.launched(&app.id);
+                }
+                self.hide();
+            }
+            Err(e) => {
+                error!("Couldn't launch {}: {}", app.name, e);
+                self.launch_failed(QString::from(e.to_string()));
+            }
+        }
     }
 
     fn hide(&mut self) {
-        self.visible = false;
+        self.set_visible(false);
+    }
+
+    /// let qml and anyone watching on the session bus know when the list comes and goes
+    fn set_visible(&mut self, visible: bool) {
+        let changed = self.visible != visible;
+        self.visible = visible;
         self.visible_changed();
+        if let (true, Some(x)) = (changed, &self.bus) {
+            x.visibility_changed(visible);
+        }
+    }
+
+    /// show the list in a mode with an empty query
+    fn open(&mut self, mode: ListType, hide_on_lost_focus: bool) {
+        self.list_type = mode;
+        self.hide_on_lost_focus = hide_on_lost_focus;
+        self.set_visible(true);
+        self.focus = true;
+        self.focus_changed();
+        self.search("".into())
     }
 
     fn show(&mut self) {
-        self.visible = true;
-        self.visible_changed();
+        self.set_visible(true);
         self.set_selected(0);
     }
     fn search(&mut self, query: String) {
         let matcher = SkimMatcherV2::default();
+        let weights = get_settings().search.weights;
         self.set(
             self.get_app_list()
                 .into_iter()
-                .map(|x| (matcher.fuzzy_match(&x.name, &query).unwrap_or(0), x))
+                .map(|x| (x.match_score(&matcher, &query, &weights), x))
                 .map(|(weight, app)| {
                     (
-                        std::cmp::min(self.usage_count.get(&app.name) as i64 * 5, 50) + weight,
+                        std::cmp::min(
+                            self.usage_count.get_or(&app.usage_key(), &app.name) as i64
+                                * weights.usage,
+                            weights.usage_max,
+                        ) + weight,
                         app,
                     )
                 })
@@ -198,7 +405,10 @@ impl SearchableList {
         match self.list_type {
             ListType::Launcher => generate_application_list()
                 .into_iter()
-                .map(|x| x.1)
+                .flat_map(|x| {
+                    let actions = x.1.actions.clone();
+                    std::iter::once(x.1).chain(actions)
+                })
                 .collect(),
             ListType::Switcher => get_running_applications(),
         }
@@ -212,9 +422,11 @@ impl SearchableList {
     }
 
     fn set(&mut self, list: Vec<Application>) {
-        self.model
-            .borrow_mut()
-            .reset_data(list.into_iter().take(9).collect());
+        self.model.borrow_mut().reset_data(
+            list.into_iter()
+                .take(get_settings().search.results)
+                .collect(),
+        );
         self.model_len = self.model.borrow().row_count();
         self.model_changed();
         self.model_len_changed();
@@ -227,6 +439,21 @@ pub struct Application {
     pub icon: String,
     pub exec: String,
     pub select: String,
+    pub(crate) id: String,
+    pub(crate) args: Vec<String>,
+    pub(crate) terminal: bool,
+    pub(crate) dbus_activatable: bool,
+    pub(crate) startup_notify: bool,
+    pub(crate) startup_wm_class: String,
+    pub(crate) working_dir: String,
+    pub(crate) untranslated_name: String,
+    pub(crate) generic_name: String,
+    pub(crate) comment: String,
+    pub(crate) keywords: Vec<String>,
+    pub(crate) categories: Vec<String>,
+    pub(crate) action: String,
+    pub(crate) actions: Vec<Application>,
+    pub(crate) visible: bool,
 }
 
 impl Application {
@@ -236,33 +463,109 @@ impl Application {
             icon,
             exec,
             select,
+            id: "".into(),
+            args: vec![],
+            terminal: false,
+            dbus_activatable: false,
+            startup_notify: false,
+            startup_wm_class: "".into(),
+            working_dir: "".into(),
+            untranslated_name: "".into(),
+            generic_name: "".into(),
+            comment: "".into(),
+            keywords: vec![],
+            categories: vec![],
+            action: "".into(),
+            actions: vec![],
+            visible: true,
         }
     }
 
-    pub fn try_exec(&self) -> bool {
-        if self.exec == "" {
-            return false;
+    /// fuzzy match the query against the name and, with lower weights, the other descriptive fields of the entry
+    pub fn match_score(&self, matcher: &SkimMatcherV2, query: &str, weights: &Weights) -> i64 {
+        let best = |fields: &[&String], weight: i64| {
+            fields
+                .iter()
+                .filter_map(|x| matcher.fuzzy_match(x, query))
+                .max()
+                .unwrap_or(0)
+                * weight
+                / 10
+        };
+        [
+            best(&[&self.name, &self.untranslated_name], weights.name),
+            best(&[&self.generic_name], weights.generic_name),
+            best(
+                &self.keywords.iter().collect::<Vec<&String>>(),
+                weights.keyword,
+            ),
+            best(
+                &self.categories.iter().collect::<Vec<&String>>(),
+                weights.category,
+            ),
+            best(&[&self.comment], weights.comment),
+        ]
+        .iter()
+        .copied()
+        .max()
+        .unwrap_or(0)
+    }
+
+    /// what launches are counted under in usage.json - the desktop file id, which unlike the name is unique
+    /// and stays the same across locales, with the action for desktop actions
+    pub fn usage_key(&self) -> String {
+        match (self.id.as_str(), self.action.as_str()) {
+            ("", _) => self.name.clone(),
+            (id, "") => id.to_string(),
+            (id, action) => format!("{}:{}", id, action),
         }
-        let cmd = self.exec.clone();
-        Application::exec_string(cmd)
     }
 
-    pub fn try_select(&self) -> bool {
-        let cmd = self.select.clone();
-        Application::exec_string(cmd)
+    pub fn try_exec(&self) -> Result<(), LauncherError> {
+        let startup = Startup::begin(self);
+        let result = self.exec_with_startup_id(startup.as_ref().map_or("", |x| x.launchee_id()));
+        if let (Err(_), Some(x)) = (&result, startup) {
+            x.cancel();
+        }
+        result
     }
 
-    fn exec_string(cmd: String) -> bool {
-        if let Err(e) = Command::new("sh")
-            .arg("-c")
-            .arg(cmd)
-            .arg("&")
-            .arg("disown")
-            .spawn()
-        {
-            error!("Couldn't launch program: {}", e);
-            return false;
+    fn exec_with_startup_id(&self, startup_id: &str) -> Result<(), LauncherError> {
+        // Exec is only a fallback for DBusActivatable entries and may not even be there
+        if self.dbus_activatable {
+            match dbus_activate(&self.id, &self.action, startup_id) {
+                Ok(()) => return Ok(()),
+                Err(e) if self.args.is_empty() => return Err(e),
+                Err(e) => warn!(
+                    "Couldn't activate {}, falling back to Exec: {}",
+                    self.name, e
+                ),
+            }
         }
-        true
+        if self.args.is_empty() {
+            return Err(LauncherError::LaunchError(format!(
+                "{} has nothing to run",
+                self.name
+            )));
+        }
+        if !self.terminal {
+            return exec_args(&self.id, &self.args, &self.working_dir, startup_id);
+        }
+        match terminal_command() {
+            Some(x) => exec_args(
+                &self.id,
+                &wrap(&x, &self.args),
+                &self.working_dir,
+                startup_id,
+            ),
+            None => Err(LauncherError::LaunchError(
+                "no terminal emulator found".into(),
+            )),
+        }
+    }
+
+    pub fn try_select(&self) -> bool {
+        let cmd = self.select.clone();
+        exec_string(cmd)
     }
 }
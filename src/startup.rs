@@ -0,0 +1,260 @@
+use crate::searchable_list::Application;
+use log::warn;
+use std::cell::RefCell;
+use std::env;
+use std::error::Error;
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use wayland_client::{Display, GlobalManager};
+use wayland_protocols::staging::xdg_activation::v1::client::xdg_activation_token_v1;
+use wayland_protocols::staging::xdg_activation::v1::client::xdg_activation_v1::XdgActivationV1;
+use x11rb::connection::Connection;
+use x11rb::protocol::xproto::{
+    ClientMessageEvent, ConnectionExt, CreateWindowAux, EventMask, WindowClass,
+    CLIENT_MESSAGE_EVENT,
+};
+use x11rb::{COPY_DEPTH_FROM_PARENT, COPY_FROM_PARENT};
+
+/// counts launches so startup ids stay unique within this process
+static SEQUENCE: AtomicUsize = AtomicUsize::new(0);
+
+/// startup notification for one launch, which lets the window manager show a busy cursor until the new window
+/// appears and then focus it - the X11 startup notification protocol, or an xdg-activation token on wayland
+pub struct Startup {
+    pub id: String,
+    /// whether the program itself reads the id from its environment (StartupNotify=true)
+    /// otherwise the window manager can only match the new window by StartupWMClass
+    notify: bool,
+    x11: bool,
+}
+
+impl Startup {
+    /// begin startup notification for an application whose entry asks for it
+    pub fn begin(app: &Application) -> Option<Startup> {
+        if !app.startup_notify && app.startup_wm_class.is_empty() {
+            return None;
+        }
+        // a token is only any use if the program passes it back to the compositor
+        if app.startup_notify && env::var_os("WAYLAND_DISPLAY").is_some() {
+            match activation_token(&app.id) {
+                Ok(id) => {
+                    return Some(Startup {
+                        id,
+                        notify: true,
+                        x11: false,
+                    })
+                }
+                Err(e) => warn!("Couldn't get an activation token for {}: {}", app.name, e),
+            }
+        }
+        if env::var_os("DISPLAY").is_some() {
+            let binary = app.args.first().unwrap_or(&app.id);
+            let id = startup_id(binary);
+            let mut fields = vec![
+                ("ID", id.clone()),
+                ("NAME", app.name.clone()),
+                ("BIN", basename(binary)),
+                ("ICON", app.icon.clone()),
+                ("DESCRIPTION", format!("Launching {}", app.name)),
+            ];
+            if !app.startup_wm_class.is_empty() {
+                fields.push(("WMCLASS", app.startup_wm_class.clone()));
+            }
+            match broadcast("new", &fields) {
+                Ok(()) => {
+                    return Some(Startup {
+                        id,
+                        notify: app.startup_notify,
+                        x11: true,
+                    })
+                }
+                Err(e) => warn!("Couldn't send startup notification for {}: {}", app.name, e),
+            }
+        }
+        None
+    }
+
+    /// the id handed to the launched program, empty if it wouldn't make use of it
+    pub fn launchee_id(&self) -> &str {
+        if self.notify {
+            &self.id
+        } else {
+            ""
+        }
+    }
+
+    /// end the startup sequence for a launch which failed, the program would normally do this once its window is mapped
+    pub fn cancel(self) {
+        if self.x11 {
+            if let Err(e) = broadcast("remove", &[("ID", self.id)]) {
+                warn!("Couldn't end startup notification: {}", e);
+            }
+        }
+    }
+}
+
+/// a startup id in the form suggested by the spec, `<launcher>-<pid>-<host>-<binary>-<sequence>`
+fn startup_id(binary: &str) -> String {
+    format!(
+        "sky-menu-{}-{}-{}-{}",
+        std::process::id(),
+        hostname(),
+        basename(binary),
+        SEQUENCE.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+fn basename(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    if unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) } != 0 {
+        return "localhost".into();
+    }
+    let len = buf.iter().position(|x| *x == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).to_string()
+}
+
+/// format a startup notification message, e.g. `new: ID="..." NAME="..."`, quoting every value
+fn format_message(kind: &str, fields: &[(&str, String)]) -> String {
+    let fields = fields
+        .iter()
+        .map(|(key, value)| {
+            format!(
+                "{}=\"{}\"",
+                key,
+                value.replace('\\', "\\\\").replace('"', "\\\"")
+            )
+        })
+        .collect::<Vec<String>>();
+    format!("{}: {}", kind, fields.join(" "))
+}
+
+/// split a message into the 20 byte pieces carried by each client message, including its nul terminator
+fn message_chunks(message: &str) -> Vec<[u8; 20]> {
+    let mut bytes = message.as_bytes().to_vec();
+    bytes.push(0);
+    bytes
+        .chunks(20)
+        .map(|x| {
+            let mut chunk = [0u8; 20];
+            chunk[..x.len()].copy_from_slice(x);
+            chunk
+        })
+        .collect()
+}
+
+/// send a startup notification message to the root window as a series of `_NET_STARTUP_INFO` client messages
+fn broadcast(kind: &str, fields: &[(&str, String)]) -> Result<(), Box<dyn Error>> {
+    let (conn, screen_num) = x11rb::connect(None)?;
+    let root = conn.setup().roots[screen_num].root;
+    let mut fields = fields.to_vec();
+    if kind == "new" {
+        fields.push(("SCREEN", screen_num.to_string()));
+    }
+
+    // the messages have to come from a window of ours, which only needs to exist while they are sent
+    let window = conn.generate_id()?;
+    conn.create_window(
+        COPY_DEPTH_FROM_PARENT,
+        window,
+        root,
+        -100,
+        -100,
+        1,
+        1,
+        0,
+        WindowClass::INPUT_ONLY,
+        COPY_FROM_PARENT,
+        &CreateWindowAux::new().override_redirect(1),
+    )?;
+    let begin = conn
+        .intern_atom(false, b"_NET_STARTUP_INFO_BEGIN")?
+        .reply()?
+        .atom;
+    let more = conn.intern_atom(false, b"_NET_STARTUP_INFO")?.reply()?.atom;
+    for (i, chunk) in message_chunks(&format_message(kind, &fields))
+        .into_iter()
+        .enumerate()
+    {
+        let event = ClientMessageEvent {
+            response_type: CLIENT_MESSAGE_EVENT,
+            format: 8,
+            sequence: 0,
+            window,
+            type_: if i == 0 { begin } else { more },
+            data: chunk.into(),
+        };
+        conn.send_event(false, root, EventMask::PROPERTY_CHANGE, event)?;
+    }
+    conn.destroy_window(window)?;
+    conn.flush()?;
+    Ok(())
+}
+
+/// ask the compositor for an xdg-activation token for a program we're about to launch
+fn activation_token(id: &str) -> Result<String, Box<dyn Error>> {
+    let display = Display::connect_to_env()?;
+    let mut queue = display.create_event_queue();
+    let attached = (*display).clone().attach(queue.token());
+    let globals = GlobalManager::new(&attached);
+    queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+
+    let activation = globals.instantiate_exact::<XdgActivationV1>(1)?;
+    let token = Rc::new(RefCell::new(None));
+    let received = token.clone();
+    let request = activation.get_activation_token();
+    request.quick_assign(move |_, event, _| {
+        if let xdg_activation_token_v1::Event::Done { token } = event {
+            *received.borrow_mut() = Some(token);
+        }
+    });
+    request.set_app_id(id.trim_end_matches(".desktop").to_string());
+    request.commit();
+    queue.sync_roundtrip(&mut (), |_, _, _| {})?;
+    request.destroy();
+    activation.destroy();
+
+    let token = token.borrow_mut().take();
+    token.ok_or_else(|| "the compositor didn't hand out a token".into())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::startup::{format_message, message_chunks, startup_id};
+
+    #[test]
+    fn it_formats_messages() {
+        assert_eq!(
+            format_message(
+                "new",
+                &[
+                    ("ID", "sky-menu-1-host-foo-0".into()),
+                    ("NAME", "Say \"hi\" \\o/".into())
+                ]
+            ),
+            "new: ID=\"sky-menu-1-host-foo-0\" NAME=\"Say \\\"hi\\\" \\\\o/\""
+        );
+
+        let chunks = message_chunks("remove: ID=\"sky-menu-1-host-foo-0\"");
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(&chunks[0], b"remove: ID=\"sky-menu");
+        assert_eq!(&chunks[1][..16], b"-1-host-foo-0\"\0\0");
+        assert_eq!(message_chunks("nineteen characters").len(), 1);
+    }
+
+    #[test]
+    fn it_makes_unique_startup_ids() {
+        let a = startup_id("/usr/bin/foo");
+        let b = startup_id("/usr/bin/foo");
+        assert!(a.starts_with(&format!("sky-menu-{}-", std::process::id())));
+        assert!(a.contains("-foo-"));
+        assert_ne!(a, b);
+    }
+}
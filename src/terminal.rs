@@ -0,0 +1,143 @@
+use crate::config::get_settings;
+use crate::utility::find_executable;
+use cached::proc_macro::cached;
+use std::env;
+use std::path::Path;
+
+/// terminal emulators to look for on PATH when TERMINAL isn't set, in order of preference
+static KNOWN_TERMINALS: &[&str] = &[
+    "x-terminal-emulator",
+    "alacritty",
+    "kitty",
+    "foot",
+    "wezterm",
+    "gnome-terminal",
+    "konsole",
+    "xfce4-terminal",
+    "terminator",
+    "tilix",
+    "urxvt",
+    "st",
+    "xterm",
+];
+
+/// the arguments a terminal emulator needs before the command it should run
+/// `{}` marks where the command goes - if it is missing the command is appended
+fn template(terminal: &str) -> Vec<String> {
+    let name = Path::new(terminal)
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let args: &[&str] = match name.as_str() {
+        "kitty" | "foot" => &[],
+        "gnome-terminal" => &["--"],
+        "wezterm" => &["start", "--"],
+        "xfce4-terminal" | "terminator" => &["-x"],
+        _ => &["-e"],
+    };
+    std::iter::once(terminal)
+        .chain(args.iter().copied())
+        .map(String::from)
+        .collect()
+}
+
+/// find the terminal to run Terminal=true applications in - `launch.terminal` from the config as it is,
+/// then TERMINAL, then the first known emulator on PATH
+pub fn terminal_command() -> Option<Vec<String>> {
+    let configured = get_settings().launch.terminal;
+    if !configured.is_empty() {
+        return Some(configured);
+    }
+    detect_terminal()
+}
+
+/// TERMINAL is usually just the program, which gets the same arguments as if it had been found on PATH,
+/// but given arguments of its own it's the whole template
+fn from_env(terminal: &str) -> Vec<String> {
+    let words = terminal
+        .split_whitespace()
+        .map(String::from)
+        .collect::<Vec<String>>();
+    match words.as_slice() {
+        [program] => template(program),
+        _ => words,
+    }
+}
+
+#[cached]
+fn detect_terminal() -> Option<Vec<String>> {
+    match env::var("TERMINAL") {
+        Ok(x) if !x.trim().is_empty() => Some(from_env(&x)),
+        _ => KNOWN_TERMINALS
+            .iter()
+            .find(|x| find_executable(x).is_some())
+            .map(|x| template(x)),
+    }
+}
+
+/// wrap a command so it runs inside the given terminal command
+pub fn wrap(terminal: &[String], args: &[String]) -> Vec<String> {
+    if terminal.iter().any(|x| x == "{}") {
+        terminal
+            .iter()
+            .flat_map(|x| {
+                if x == "{}" {
+                    args.to_vec()
+                } else {
+                    vec![x.clone()]
+                }
+            })
+            .collect()
+    } else {
+        terminal.iter().chain(args).cloned().collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::terminal::{from_env, template, wrap};
+
+    fn strings(x: &[&str]) -> Vec<String> {
+        x.iter().map(|y| y.to_string()).collect()
+    }
+
+    #[test]
+    fn it_picks_terminal_templates() {
+        assert_eq!(template("xterm"), strings(&["xterm", "-e"]));
+        assert_eq!(template("/usr/bin/kitty"), strings(&["/usr/bin/kitty"]));
+        assert_eq!(
+            template("gnome-terminal"),
+            strings(&["gnome-terminal", "--"])
+        );
+    }
+
+    #[test]
+    fn it_reads_terminal_from_env() {
+        assert_eq!(from_env("xterm"), strings(&["xterm", "-e"]));
+        assert_eq!(from_env(" kitty "), strings(&["kitty"]));
+        assert_eq!(
+            from_env("alacritty --class float -e"),
+            strings(&["alacritty", "--class", "float", "-e"])
+        );
+        assert_eq!(
+            from_env("tmux new-window {}"),
+            strings(&["tmux", "new-window", "{}"])
+        );
+    }
+
+    #[test]
+    fn it_wraps_commands() {
+        let command = strings(&["htop", "-d", "10"]);
+        assert_eq!(
+            wrap(&strings(&["alacritty", "-e"]), &command),
+            strings(&["alacritty", "-e", "htop", "-d", "10"])
+        );
+        assert_eq!(
+            wrap(
+                &strings(&["tmux", "new-window", "{}", ";", "attach"]),
+                &command
+            ),
+            strings(&["tmux", "new-window", "htop", "-d", "10", ";", "attach"])
+        );
+    }
+}
@@ -0,0 +1,57 @@
+use dbus::blocking::{Connection, SyncConnection};
+use dbus::channel::Channel;
+use std::io::{BufRead, BufReader};
+use std::process::{Child, Command, Stdio};
+
+/// a private dbus-daemon for tests which need a session bus, killed when dropped
+pub struct PrivateBus {
+    daemon: Child,
+    pub address: String,
+}
+
+impl PrivateBus {
+    pub fn new() -> Self {
+        let mut daemon = Command::new("dbus-daemon")
+            .args(["--session", "--nofork", "--print-address=1"])
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("couldn't start dbus-daemon");
+        let mut address = String::new();
+        BufReader::new(daemon.stdout.take().unwrap())
+            .read_line(&mut address)
+            .expect("couldn't read the dbus-daemon address");
+        PrivateBus {
+            daemon,
+            address: address.trim().to_string(),
+        }
+    }
+
+    pub fn connect(&self) -> Connection {
+        PrivateBus::connect_to(&self.address)
+    }
+
+    /// a connection which can be shared between threads, for serving on the bus
+    pub fn connect_sync(&self) -> SyncConnection {
+        SyncConnection::from(PrivateBus::channel(&self.address))
+    }
+
+    pub fn connect_to(address: &str) -> Connection {
+        Connection::from(PrivateBus::channel(address))
+    }
+
+    fn channel(address: &str) -> Channel {
+        let mut channel = Channel::open_private(address).expect("couldn't connect to dbus-daemon");
+        channel
+            .register()
+            .expect("couldn't register with dbus-daemon");
+        channel
+    }
+}
+
+impl Drop for PrivateBus {
+    fn drop(&mut self) {
+        let _ = self.daemon.kill();
+        let _ = self.daemon.wait();
+    }
+}
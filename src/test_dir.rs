@@ -0,0 +1,39 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs, process};
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// a fresh directory for tests which need files on disk, removed when dropped even if the test fails
+pub struct TempDir {
+    path: PathBuf,
+}
+
+impl TempDir {
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!(
+            "sky-menu-{}-{}-{}",
+            name,
+            process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        // left behind by a run which was killed before it could clean up
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).expect("couldn't create a test directory");
+        TempDir { path }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join<P: AsRef<Path>>(&self, path: P) -> PathBuf {
+        self.path.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.path);
+    }
+}
@@ -3,17 +3,73 @@ use i3ipc::reply::{Node, WindowProperty};
 use i3ipc::I3Connection;
 use std::collections::HashMap;
 use std::env;
-use std::path::PathBuf;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 
+/// the user specific data directory, XDG_DATA_HOME or ~/.local/share if unset
+pub fn get_xdg_data_home() -> Option<PathBuf> {
+    match env::var("XDG_DATA_HOME") {
+        Ok(x) if !x.is_empty() => Some(PathBuf::from(x)),
+        _ => env::var("HOME")
+            .ok()
+            .map(|x| PathBuf::from(x).join(".local/share")),
+    }
+}
+
+/// all data directories in order of precedence - XDG_DATA_HOME first, then each of XDG_DATA_DIRS
 pub fn get_xdg_data_dirs() -> impl Iterator<Item = PathBuf> {
-    env::var("XDG_DATA_DIRS")
-        .unwrap_or("/usr/local/share/:/usr/share/".into())
-        .split(":")
-        .map(PathBuf::from)
+    let data_dirs = match env::var("XDG_DATA_DIRS") {
+        Ok(x) if !x.is_empty() => x,
+        _ => "/usr/local/share/:/usr/share/".into(),
+    };
+    get_xdg_data_home()
+        .into_iter()
+        .chain(
+            data_dirs
+                .split(":")
+                .filter(|x| !x.is_empty())
+                .map(PathBuf::from),
+        )
         .collect::<Vec<PathBuf>>() // FIXME why do i need to collect here
         .into_iter()
 }
 
+/// the desktop environments named in XDG_CURRENT_DESKTOP, used for OnlyShowIn / NotShowIn
+pub fn get_xdg_current_desktops() -> Vec<String> {
+    env::var("XDG_CURRENT_DESKTOP")
+        .unwrap_or_default()
+        .split(":")
+        .filter(|x| !x.is_empty())
+        .map(String::from)
+        .collect()
+}
+
+/// the locale used for messages, taken from the first of LC_ALL, LC_MESSAGES and LANG that is set
+pub fn get_messages_locale() -> String {
+    ["LC_ALL", "LC_MESSAGES", "LANG"]
+        .iter()
+        .filter_map(|x| env::var(x).ok())
+        .find(|x| !x.is_empty())
+        .unwrap_or_default()
+}
+
+/// resolve a program name against PATH the way a shell would - names containing a slash are used as is
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let is_executable = |x: &Path| {
+        x.metadata()
+            .is_ok_and(|y| y.is_file() && y.permissions().mode() & 0o111 != 0)
+    };
+    if name.contains('/') {
+        return Some(PathBuf::from(name)).filter(|x| is_executable(x));
+    }
+    env::var("PATH")
+        .unwrap_or_default()
+        .split(":")
+        .filter(|x| !x.is_empty())
+        .map(|x| Path::new(x).join(name))
+        .find(|x| is_executable(x))
+}
+
 pub fn get_xdg_application_dirs() -> impl Iterator<Item = PathBuf> {
     get_xdg_data_dirs()
         .into_iter()
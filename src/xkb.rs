@@ -0,0 +1,124 @@
+use crate::config::KeyboardSettings;
+use crate::keyboard_listener::XkbEvent;
+use xkbcommon::xkb;
+
+pub const CTRL: &str = xkb::MOD_NAME_CTRL;
+pub const SHIFT: &str = xkb::MOD_NAME_SHIFT;
+pub const ALT: &str = xkb::MOD_NAME_ALT;
+pub const LOGO: &str = xkb::MOD_NAME_LOGO;
+
+/// the modifiers hotkeys can use, by their xkbcommon names
+pub const MODIFIERS: [&str; 4] = [CTRL, SHIFT, ALT, LOGO];
+
+/// keyboard state kept by xkbcommon, which knows the layout so hotkeys can match on keysyms rather than scan codes
+pub struct XkbKeyboard {
+    state: xkb::State,
+}
+
+impl XkbKeyboard {
+    /// compile a keymap from the RMLVO names in the config, empty names fall back on the system defaults
+    pub fn new(settings: &KeyboardSettings) -> Option<XkbKeyboard> {
+        let context = xkb::Context::new(xkb::CONTEXT_NO_FLAGS);
+        let options = Some(settings.options.clone()).filter(|x| !x.is_empty());
+        let keymap = xkb::Keymap::new_from_names(
+            &context,
+            &settings.rules,
+            &settings.model,
+            &settings.layout,
+            &settings.variant,
+            options,
+            xkb::COMPILE_NO_FLAGS,
+        )?;
+        Some(XkbKeyboard {
+            state: xkb::State::new(&keymap),
+        })
+    }
+
+    /// feed a key press or release through the state, `key` being its evdev code
+    pub fn update(&mut self, key: u32, pressed: bool) -> XkbEvent {
+        // xkb keycodes are evdev codes offset by 8, a leftover from X11
+        let keycode = xkb::Keycode::new(key + 8);
+        let previous_modifiers = self.active_modifiers();
+        self.state.update_key(
+            keycode,
+            if pressed {
+                xkb::KeyDirection::Down
+            } else {
+                xkb::KeyDirection::Up
+            },
+        );
+        // the first level, so Ctrl+Shift+T still reports t rather than T
+        let keysym = self
+            .state
+            .get_keymap()
+            .key_get_syms_by_level(keycode, self.state.key_get_layout(keycode), 0)
+            .first()
+            .map_or(0, |x| x.raw());
+        XkbEvent {
+            keysym,
+            modifiers: self.active_modifiers(),
+            previous_modifiers,
+        }
+    }
+
+    fn active_modifiers(&self) -> Vec<&'static str> {
+        MODIFIERS
+            .iter()
+            .copied()
+            .filter(|x| self.state.mod_name_is_active(x, xkb::STATE_MODS_EFFECTIVE))
+            .collect()
+    }
+}
+
+/// look up a keysym by name, ignoring case if there is no exact match
+pub fn keysym_from_name(name: &str) -> Option<u32> {
+    [xkb::KEYSYM_NO_FLAGS, xkb::KEYSYM_CASE_INSENSITIVE]
+        .iter()
+        .map(|x| xkb::keysym_from_name(name, *x).raw())
+        .find(|x| *x != xkb::keysyms::KEY_NoSymbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::KeyboardSettings;
+    use crate::xkb::{keysym_from_name, XkbKeyboard, CTRL, SHIFT};
+    use xkbcommon::xkb;
+
+    const LCTRL: u32 = 29;
+    const LSHIFT: u32 = 42;
+    const K: u32 = 37;
+
+    #[test]
+    fn it_looks_up_keysyms() {
+        assert_eq!(keysym_from_name("space"), Some(xkb::keysyms::KEY_space));
+        assert_eq!(keysym_from_name("Return"), Some(xkb::keysyms::KEY_Return));
+        assert_eq!(keysym_from_name("t"), Some(xkb::keysyms::KEY_t));
+        assert_eq!(keysym_from_name("f1"), Some(xkb::keysyms::KEY_F1));
+        assert_eq!(keysym_from_name("nosuchkey"), None);
+    }
+
+    #[test]
+    fn it_follows_the_layout() {
+        let settings = |variant: &str| KeyboardSettings {
+            xkb: true,
+            layout: "us".into(),
+            variant: variant.into(),
+            ..KeyboardSettings::default()
+        };
+
+        let mut qwerty = XkbKeyboard::new(&settings("")).unwrap();
+        assert_eq!(qwerty.update(K, true).keysym, xkb::keysyms::KEY_k);
+
+        // the same physical key is t on dvorak, and shift doesn't turn it into T
+        let mut dvorak = XkbKeyboard::new(&settings("dvorak")).unwrap();
+        dvorak.update(LCTRL, true);
+        let event = dvorak.update(LSHIFT, true);
+        assert_eq!(event.previous_modifiers, vec![CTRL]);
+        assert_eq!(event.modifiers, vec![CTRL, SHIFT]);
+        assert_eq!(dvorak.update(K, true).keysym, xkb::keysyms::KEY_t);
+        let event = dvorak.update(LCTRL, false);
+        assert_eq!(event.modifiers, vec![SHIFT]);
+
+        assert!(XkbKeyboard::new(&settings("nosuchvariant")).is_none());
+    }
+}